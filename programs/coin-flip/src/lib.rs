@@ -2,15 +2,15 @@ use anchor_lang::{
     prelude::*,
     solana_program::{
         sysvar::{
-            recent_blockhashes,
+            instructions,
             slot_hashes,
         },
-        program_memory::sol_memset,
     },
 };
-use anchor_spl::token::{self, Burn, Mint, MintTo, Token, TokenAccount, Transfer};
-use std::hash::{Hash, Hasher};
-use std::collections::hash_map::DefaultHasher;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token::{self, Burn, CloseAccount, Mint, MintTo, Token, TokenAccount, Transfer},
+};
 
 declare_id!("FNEAGNqHuUWzfY4njXBNHi7ABVT2XiLqW9HsS1JXV5uN");
 
@@ -19,8 +19,37 @@ pub const CORE_STATE_SEED: &str = "core-state";
 pub const VAULT_AUTH_SEED: &str = "vault-auth";
 pub const VAULT_TOKEN_ACCOUNT_SEED: &str = "vault-token-account";
 pub const BET_STATE_SEED: &str = "bet-state";
+pub const COMMITMENT_QUEUE_SEED: &str = "commitment-queue";
+pub const MINT_LEDGER_SEED: &str = "mint-ledger";
+pub const RENT_SPONSOR_SEED: &str = "rent-sponsor";
+pub const PENDING_CONFIG_SEED: &str = "pending-config";
+pub const FEE_VAULT_SEED: &str = "fee-vault";
+pub const REFERRAL_LINK_SEED: &str = "referral-link";
+pub const REFERRER_SEED: &str = "referrer";
+pub const PLAYER_STATS_SEED: &str = "player-stats";
+pub const LP_MINT_SEED: &str = "lp-mint";
+pub const USER_EXPOSURE_SEED: &str = "user-exposure";
+pub const LOYALTY_MINT_SEED: &str = "loyalty-mint";
+
+pub const MAX_COMMITMENTS: usize = 32;
+pub const MAX_EXECUTERS: usize = 5;
+pub const MAX_WITHDRAW_DESTINATIONS: usize = 5;
+pub const MAX_PLAYER_STATS_MINTS: usize = 8;
+pub const DEFAULT_SETTLE_TIMEOUT: i64 = 60 * 60; // seconds
+#[cfg(not(feature = "short-config-delay"))]
+pub const MIN_CONFIG_DELAY: i64 = 60 * 60; // seconds
+// for localnet tests, which cannot wait an hour to apply a config
+#[cfg(feature = "short-config-delay")]
+pub const MIN_CONFIG_DELAY: i64 = 5; // seconds
+// virtual offsets in the LP share price, so a first depositor cannot inflate it. They
+// are equal so that shares start out 1:1 with the vault's base units.
+pub const LP_VIRTUAL_SHARES: u64 = 1_000;
+pub const LP_VIRTUAL_ASSETS: u64 = 1_000;
 
 pub mod utils;
+pub mod randomness;
+
+use randomness::{Game, RandomnessMode};
 
 #[program]
 pub mod coin_flip {
@@ -28,35 +57,534 @@ pub mod coin_flip {
 
     pub fn initialize(ctx: Context<Initialize>, args: InitializeArgs) -> Result<()> {
         ctx.accounts.core_state.admin = ctx.accounts.admin.key();
-        ctx.accounts.core_state.executer = ctx.accounts.executer.key();
+        ctx.accounts.core_state.house_id = args.house_id;
+        ctx.accounts.core_state.pauser = ctx.accounts.admin.key();
+        ctx.accounts.core_state.treasurer = ctx.accounts.admin.key();
+        ctx.accounts.core_state.risk_manager = ctx.accounts.admin.key();
+        ctx.accounts.core_state.withdraw_destinations = vec![ctx.accounts.admin.key()];
+        ctx.accounts.core_state.executers = vec![ctx.accounts.executer.key()];
         ctx.accounts.core_state.core_state_nonce = args.core_state_nonce;
         ctx.accounts.core_state.vault_auth_nonce = args.vault_auth_nonce;
         ctx.accounts.core_state.flip_counter = 0;
+        if args.fee_percent > 10000 {
+            return Err(ErrorCode::InvalidFeePercent.into());
+        }
         ctx.accounts.core_state.fee_percent = args.fee_percent;
         ctx.accounts.core_state.win_ratio = args.win_ratio;
         ctx.accounts.core_state.active = true;
         ctx.accounts.core_state.allow_direct_bet = true;
+        ctx.accounts.core_state.oracle = args.oracle;
+        randomness::validate_mode(args.randomness_mode, &args.oracle)?;
+        ctx.accounts.core_state.randomness_mode = args.randomness_mode;
+        ctx.accounts.core_state.settle_timeout = DEFAULT_SETTLE_TIMEOUT;
+        if args.config_delay < MIN_CONFIG_DELAY {
+            return Err(ErrorCode::InvalidConfigDelay.into());
+        }
+        ctx.accounts.core_state.config_delay = args.config_delay;
+        utils::assert_rtp(args.win_ratio, args.payout_multiplier_bps, args.max_rtp_bps)?;
+        ctx.accounts.core_state.payout_multiplier_bps = args.payout_multiplier_bps;
+        ctx.accounts.core_state.max_rtp_bps = args.max_rtp_bps;
+        utils::assert_dice_edge(args.dice_edge_bps, args.max_rtp_bps)?;
+        ctx.accounts.core_state.dice_edge_bps = args.dice_edge_bps;
         Ok(())
     }
 
     pub fn update_core_state(ctx: Context<UpdateCoreState>, args: UpdateCoreStateArgs) -> Result<()> {
-        ctx.accounts.core_state.fee_percent = args.fee_percent;
         ctx.accounts.core_state.active = args.active;
         ctx.accounts.core_state.allow_direct_bet = args.allow_direct_bet;
+        ctx.accounts.core_state.oracle = args.oracle;
+        randomness::validate_mode(args.randomness_mode, &args.oracle)?;
+        ctx.accounts.core_state.randomness_mode = args.randomness_mode;
+        if args.settle_timeout <= 0 {
+            return Err(ErrorCode::InvalidSettleTimeout.into());
+        }
+        ctx.accounts.core_state.settle_timeout = args.settle_timeout;
+        ctx.accounts.core_state.admin_funded_rent = args.admin_funded_rent;
+        utils::assert_rtp(ctx.accounts.core_state.win_ratio, args.payout_multiplier_bps, args.max_rtp_bps)?;
+        ctx.accounts.core_state.payout_multiplier_bps = args.payout_multiplier_bps;
+        ctx.accounts.core_state.max_rtp_bps = args.max_rtp_bps;
+        utils::assert_dice_edge(args.dice_edge_bps, args.max_rtp_bps)?;
+        ctx.accounts.core_state.dice_edge_bps = args.dice_edge_bps;
+        if args.referral_share_bps > 10000 {
+            return Err(ErrorCode::InvalidReferralShare.into());
+        }
+        ctx.accounts.core_state.referral_share_bps = args.referral_share_bps;
+        emit_core_state_updated(&ctx.accounts.core_state, ctx.accounts.admin.key());
+        Ok(())
+    }
+
+    /// Queues a win_ratio / fee_percent / config_delay change, replacing any queued one.
+    /// It can be applied once the current config_delay has passed.
+    pub fn propose_config(ctx: Context<ProposeConfig>, args: ProposeConfigArgs) -> Result<()> {
+        let core_state = &ctx.accounts.core_state;
+        utils::assert_rtp(args.win_ratio, core_state.payout_multiplier_bps, core_state.max_rtp_bps)?;
+        if args.fee_percent > 10000 {
+            return Err(ErrorCode::InvalidFeePercent.into());
+        }
+        if args.config_delay < MIN_CONFIG_DELAY {
+            return Err(ErrorCode::InvalidConfigDelay.into());
+        }
+
+        let effective_at = (Clock::get()?).unix_timestamp
+            .checked_add(core_state.config_delay)
+            .ok_or(ErrorCode::NumericalOverflow)?;
+        ctx.accounts.pending_config.core_state = core_state.key();
+        ctx.accounts.pending_config.win_ratio = args.win_ratio;
+        ctx.accounts.pending_config.fee_percent = args.fee_percent;
+        ctx.accounts.pending_config.config_delay = args.config_delay;
+        ctx.accounts.pending_config.effective_at = effective_at;
+        Ok(())
+    }
+
+    /// Permissionless. Moves a queued config into CoreState and closes the PendingConfig.
+    pub fn apply_config(ctx: Context<ApplyConfig>) -> Result<()> {
+        let pending_config = &ctx.accounts.pending_config;
+        if (Clock::get()?).unix_timestamp < pending_config.effective_at {
+            return Err(ErrorCode::ConfigNotReady.into());
+        }
+        // payout_multiplier_bps and max_rtp_bps may have changed since the proposal
+        utils::assert_rtp(
+            pending_config.win_ratio,
+            ctx.accounts.core_state.payout_multiplier_bps,
+            ctx.accounts.core_state.max_rtp_bps,
+        )?;
+
+        ctx.accounts.core_state.win_ratio = pending_config.win_ratio;
+        ctx.accounts.core_state.fee_percent = pending_config.fee_percent;
+        ctx.accounts.core_state.config_delay = pending_config.config_delay;
+        emit_core_state_updated(&ctx.accounts.core_state, ctx.accounts.admin.key());
+        Ok(())
+    }
+
+    pub fn set_roles(ctx: Context<UpdateCoreState>, args: SetRolesArgs) -> Result<()> {
+        if args.withdraw_destinations.len() > MAX_WITHDRAW_DESTINATIONS {
+            return Err(ErrorCode::TooManyDestinations.into());
+        }
+        ctx.accounts.core_state.pauser = args.pauser;
+        ctx.accounts.core_state.treasurer = args.treasurer;
+        ctx.accounts.core_state.risk_manager = args.risk_manager;
+        ctx.accounts.core_state.withdraw_destinations = args.withdraw_destinations;
+        Ok(())
+    }
+
+    pub fn set_active(ctx: Context<SetActive>, args: SetActiveArgs) -> Result<()> {
+        ctx.accounts.core_state.active = args.active;
+        emit_core_state_updated(&ctx.accounts.core_state, ctx.accounts.pauser.key());
+        Ok(())
+    }
+
+    pub fn add_executer(ctx: Context<UpdateCoreState>, args: ExecuterArgs) -> Result<()> {
+        let executers = &mut ctx.accounts.core_state.executers;
+
+        if executers.contains(&args.executer) {
+            return Err(ErrorCode::ExecuterAlreadyAdded.into());
+        }
+        if executers.len() >= MAX_EXECUTERS {
+            return Err(ErrorCode::ExecuterListFull.into());
+        }
+        executers.push(args.executer);
+        Ok(())
+    }
+
+    pub fn remove_executer(ctx: Context<UpdateCoreState>, args: ExecuterArgs) -> Result<()> {
+        let executers = &mut ctx.accounts.core_state.executers;
+
+        let index = executers
+            .iter()
+            .position(|executer| *executer == args.executer)
+            .ok_or(ErrorCode::ExecuterNotFound)?;
+        executers.remove(index);
+        Ok(())
+    }
+
+    pub fn propose_admin(ctx: Context<UpdateCoreState>, args: ProposeAdminArgs) -> Result<()> {
+        ctx.accounts.core_state.pending_admin = args.new_admin;
+        Ok(())
+    }
+
+    pub fn accept_admin(ctx: Context<AcceptAdmin>) -> Result<()> {
+        ctx.accounts.core_state.admin = ctx.accounts.new_admin.key();
+        ctx.accounts.core_state.pending_admin = Pubkey::default();
         Ok(())
     }
 
     pub fn register(ctx: Context<Register>, args: RegisterArgs) -> Result<()> {
         ctx.accounts.allowed_bets.amounts = args.amounts;
+        utils::assert_valid_amount_range(&args.amount_range)?;
+        ctx.accounts.allowed_bets.amount_range = args.amount_range;
         ctx.accounts.allowed_bets.token_mint = ctx.accounts.token_mint.key();
+        ctx.accounts.allowed_bets.enabled = true;
+        ctx.accounts.allowed_bets.max_payout_bps = args.max_payout_bps;
+        ctx.accounts.allowed_bets.max_user_liability = args.max_user_liability;
+        ctx.accounts.allowed_bets.max_window_volume = args.max_window_volume;
+        ctx.accounts.allowed_bets.volume_window_slots = args.volume_window_slots;
+
+        let is_native = ctx.accounts.token_mint.key() == spl_token::native_mint::id();
+        ctx.accounts.mint_ledger.token_mint = ctx.accounts.token_mint.key();
+        ctx.accounts.mint_ledger.vault = if is_native {
+            ctx.accounts.vault_authority.key()
+        } else {
+            ctx.accounts.vault_token_account.key()
+        };
+        ctx.accounts.mint_ledger.fee_vault = if is_native {
+            ctx.accounts.native_fee_vault.key()
+        } else {
+            ctx.accounts.fee_vault_token_account.key()
+        };
+        ctx.accounts.mint_ledger.fee_vault_nonce = *ctx.bumps.get("native_fee_vault").unwrap();
+
+        // the native fee vault holds bare lamports, so it has to stay rent exempt
+        let rent_reserve = Rent::get()?.minimum_balance(0);
+        let native_fee_vault = &ctx.accounts.native_fee_vault;
+        if is_native && native_fee_vault.lamports() < rent_reserve {
+            utils::transfer_to_vault(
+                &ctx.accounts.admin.to_account_info(),
+                native_fee_vault,
+                &ctx.accounts.admin.to_account_info(),
+                &ctx.accounts.token_program,
+                &ctx.accounts.system_program,
+                rent_reserve - native_fee_vault.lamports(),
+                true,
+            )?;
+        }
+
+        emit!(MintRegistered {
+            user: ctx.accounts.admin.key(),
+            token_mint: ctx.accounts.token_mint.key(),
+            flip_counter: ctx.accounts.core_state.flip_counter,
+            vault: ctx.accounts.mint_ledger.vault,
+        });
+
         Ok(())
     }
 
-    pub fn deposit(ctx: Context<Deposit>, args: DepositArgs) -> Result<()> {
+    pub fn update_allowed_bets(ctx: Context<UpdateAllowedBets>, args: UpdateAllowedBetsArgs) -> Result<()> {
+        utils::resize_account(
+            &ctx.accounts.allowed_bets.to_account_info(),
+            &ctx.accounts.risk_manager.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            AllowedBets::space(args.amounts.len()),
+        )?;
+        ctx.accounts.allowed_bets.amounts = args.amounts;
+        utils::assert_valid_amount_range(&args.amount_range)?;
+        ctx.accounts.allowed_bets.amount_range = args.amount_range;
+        Ok(())
+    }
+
+    pub fn set_mint_enabled(ctx: Context<UpdateAllowedBets>, args: SetMintEnabledArgs) -> Result<()> {
+        ctx.accounts.allowed_bets.enabled = args.enabled;
+        Ok(())
+    }
+
+    pub fn deregister_mint(ctx: Context<DeregisterMint>) -> Result<()> {
+        let core_state = &ctx.accounts.core_state;
+        let admin = &ctx.accounts.admin;
+        let destination = &ctx.accounts.destination;
+        let token_mint = &ctx.accounts.token_mint;
+        let destination_token_account = &ctx.accounts.destination_token_account;
+        let vault_authority = &ctx.accounts.vault_authority;
+        let vault_token_account = &ctx.accounts.vault_token_account;
+
+        let is_native = token_mint.key() == spl_token::native_mint::id();
+        core_state.assert_withdraw_destination(&destination.key())?;
+        if !is_native {
+            utils::assert_is_ata(destination_token_account, &destination.key(), &token_mint.key())?;
+        } else {
+            utils::assert_keys_equal(destination_token_account.key(), destination.key())?;
+        }
+        let vault = if is_native {
+            vault_authority.to_account_info()
+        } else {
+            vault_token_account.to_account_info()
+        };
+
+        let vault_auth_seeds = [
+            VAULT_AUTH_SEED.as_bytes(),
+            core_state.house_id.as_ref(),
+            &[core_state.vault_auth_nonce],
+        ];
+
+        // sweep what is left in the vault to a withdraw destination
+        let vault_balance = utils::vault_balance(&vault, is_native)?;
+        if vault_balance > 0 {
+            utils::transfer_from_vault(
+                &vault,
+                destination_token_account,
+                vault_authority,
+                &ctx.accounts.token_program,
+                &ctx.accounts.system_program,
+                &vault_auth_seeds,
+                vault_balance,
+                is_native,
+            )?;
+        }
+
+        // unclaimed fees go the same way
+        let fee_vault = if is_native {
+            ctx.accounts.native_fee_vault.to_account_info()
+        } else {
+            ctx.accounts.fee_vault_token_account.to_account_info()
+        };
+        let fee_balance = utils::vault_balance(&fee_vault, is_native)?;
+        if fee_balance > 0 {
+            utils::transfer_from_fee_vault(
+                &fee_vault,
+                destination_token_account,
+                vault_authority,
+                &ctx.accounts.token_program,
+                &ctx.accounts.system_program,
+                core_state,
+                ctx.accounts.mint_ledger.fee_vault_nonce,
+                fee_balance,
+                is_native,
+            )?;
+        }
+
+        for account in [vault_token_account.to_account_info(), ctx.accounts.fee_vault_token_account.to_account_info()] {
+            token::close_account(CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                CloseAccount {
+                    account,
+                    destination: admin.to_account_info(),
+                    authority: vault_authority.to_account_info(),
+                },
+                &[&vault_auth_seeds],
+            ))?;
+        }
+
+        Ok(())
+    }
+
+    /// Pays collected fees out of the fee vault to a withdraw destination.
+    pub fn claim_fees(ctx: Context<ClaimFees>, args: ClaimFeesArgs) -> Result<()> {
+        let core_state = &ctx.accounts.core_state;
+        let destination = &ctx.accounts.destination;
+        let token_mint = &ctx.accounts.token_mint;
+        let destination_token_account = &ctx.accounts.destination_token_account;
+        let fee_vault = &ctx.accounts.fee_vault;
+
+        let is_native = token_mint.key() == spl_token::native_mint::id();
+        core_state.assert_withdraw_destination(&destination.key())?;
+        if !is_native {
+            utils::assert_is_ata(destination_token_account, &destination.key(), &token_mint.key())?;
+        } else {
+            utils::assert_keys_equal(destination_token_account.key(), destination.key())?;
+        }
+
+        // accrued referral rewards are paid from the same vault, and the fees of pending
+        // bets go back out if they are refunded
+        let claimable = utils::vault_balance(fee_vault, is_native)?
+            .saturating_sub(ctx.accounts.mint_ledger.referral_owed)
+            .saturating_sub(ctx.accounts.mint_ledger.pending_fees);
+        if claimable < args.amount {
+            return Err(ErrorCode::InsufficientFees.into());
+        }
+        utils::transfer_from_fee_vault(
+            fee_vault,
+            destination_token_account,
+            &ctx.accounts.vault_authority,
+            &ctx.accounts.token_program,
+            &ctx.accounts.system_program,
+            core_state,
+            ctx.accounts.mint_ledger.fee_vault_nonce,
+            args.amount,
+            is_native,
+        )?;
+        ctx.accounts.mint_ledger.record_fee_claim(args.amount)?;
+
+        emit!(FeesClaimed {
+            user: ctx.accounts.treasurer.key(),
+            token_mint: token_mint.key(),
+            flip_counter: core_state.flip_counter,
+            destination: destination.key(),
+            amount: args.amount,
+        });
+
+        Ok(())
+    }
+
+    /// Links the signer to a referrer. There is one link per user, it cannot be changed.
+    pub fn register_referral(ctx: Context<RegisterReferral>, args: RegisterReferralArgs) -> Result<()> {
+        if args.referrer == ctx.accounts.user.key() {
+            return Err(ErrorCode::InvalidReferrer.into());
+        }
+        ctx.accounts.referral_link.core_state = ctx.accounts.core_state.key();
+        ctx.accounts.referral_link.user = ctx.accounts.user.key();
+        ctx.accounts.referral_link.referrer = args.referrer;
+        Ok(())
+    }
+
+    pub fn init_referrer(ctx: Context<InitReferrer>) -> Result<()> {
+        ctx.accounts.referrer_account.core_state = ctx.accounts.core_state.key();
+        ctx.accounts.referrer_account.referrer = ctx.accounts.referrer.key();
+        ctx.accounts.referrer_account.token_mint = ctx.accounts.token_mint.key();
+        Ok(())
+    }
+
+    /// Pays a referrer's accrued share of fees out of the fee vault.
+    pub fn claim_referral_rewards(ctx: Context<ClaimReferralRewards>) -> Result<()> {
+        let core_state = &ctx.accounts.core_state;
+        let referrer = &ctx.accounts.referrer;
+        let token_mint = &ctx.accounts.token_mint;
+        let referrer_token_account = &ctx.accounts.referrer_token_account;
+
+        let is_native = token_mint.key() == spl_token::native_mint::id();
+        if !is_native {
+            utils::assert_is_ata(referrer_token_account, &referrer.key(), &token_mint.key())?;
+        } else {
+            utils::assert_keys_equal(referrer_token_account.key(), referrer.key())?;
+        }
+
+        let amount = ctx.accounts.referrer_account.accrued;
+        if amount == 0 {
+            return Err(ErrorCode::NothingToClaim.into());
+        }
+        utils::transfer_from_fee_vault(
+            &ctx.accounts.fee_vault,
+            referrer_token_account,
+            &ctx.accounts.vault_authority,
+            &ctx.accounts.token_program,
+            &ctx.accounts.system_program,
+            core_state,
+            ctx.accounts.mint_ledger.fee_vault_nonce,
+            amount,
+            is_native,
+        )?;
+        ctx.accounts.referrer_account.accrued = 0;
+        ctx.accounts.mint_ledger.record_referral_claim(amount)?;
+
+        emit!(ReferralRewardsClaimed {
+            user: referrer.key(),
+            token_mint: token_mint.key(),
+            flip_counter: core_state.flip_counter,
+            amount,
+        });
+
+        Ok(())
+    }
+
+    /// Points are earned in proportion to the fees paid while a loyalty mint is set.
+    pub fn set_loyalty_mint(ctx: Context<SetLoyaltyMint>, args: SetLoyaltyMintArgs) -> Result<()> {
+        if args.loyalty_rate_bps > 10000 {
+            return Err(ErrorCode::InvalidLoyaltyRate.into());
+        }
+        ctx.accounts.core_state.loyalty_mint = ctx.accounts.loyalty_mint.key();
+        ctx.accounts.core_state.loyalty_rate_bps = args.loyalty_rate_bps;
+        ctx.accounts.core_state.points_per_free_bet = args.points_per_free_bet;
+        Ok(())
+    }
+
+    /// Mints the loyalty points a player has earned into their token account.
+    pub fn claim_loyalty_points(ctx: Context<ClaimLoyaltyPoints>) -> Result<()> {
+        let points = ctx.accounts.player_stats.loyalty_points;
+        if points == 0 {
+            return Err(ErrorCode::NothingToClaim.into());
+        }
+
+        let vault_auth_seeds = [
+            VAULT_AUTH_SEED.as_bytes(),
+            ctx.accounts.core_state.house_id.as_ref(),
+            &[ctx.accounts.core_state.vault_auth_nonce],
+        ];
+        token::mint_to(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                MintTo {
+                    mint: ctx.accounts.loyalty_mint.to_account_info(),
+                    to: ctx.accounts.user_loyalty_account.to_account_info(),
+                    authority: ctx.accounts.vault_authority.to_account_info(),
+                },
+                &[&vault_auth_seeds],
+            ),
+            points,
+        )?;
+        ctx.accounts.player_stats.loyalty_points = 0;
+        Ok(())
+    }
+
+    /// Burns points_per_free_bet points for each bet the player can later place without a fee.
+    pub fn redeem_loyalty_points(ctx: Context<RedeemLoyaltyPoints>, args: RedeemLoyaltyPointsArgs) -> Result<()> {
+        let core_state = &ctx.accounts.core_state;
+        if core_state.points_per_free_bet == 0 || args.free_bets == 0 {
+            return Err(ErrorCode::LoyaltyNotEnabled.into());
+        }
+        let points = args.free_bets
+            .checked_mul(core_state.points_per_free_bet)
+            .ok_or(ErrorCode::NumericalOverflow)?;
+
+        token::burn(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Burn {
+                    mint: ctx.accounts.loyalty_mint.to_account_info(),
+                    to: ctx.accounts.user_loyalty_account.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
+                },
+            ),
+            points,
+        )?;
+        ctx.accounts.player_stats.free_fee_bets = ctx.accounts.player_stats.free_fee_bets
+            .checked_add(args.free_bets)
+            .ok_or(ErrorCode::NumericalOverflow)?;
+        Ok(())
+    }
+
+    pub fn set_risk_limits(ctx: Context<SetRiskLimits>, args: SetRiskLimitsArgs) -> Result<()> {
+        ctx.accounts.allowed_bets.max_payout_bps = args.max_payout_bps;
+        ctx.accounts.allowed_bets.max_user_liability = args.max_user_liability;
+        ctx.accounts.allowed_bets.max_window_volume = args.max_window_volume;
+        ctx.accounts.allowed_bets.volume_window_slots = args.volume_window_slots;
+        Ok(())
+    }
+
+    pub fn init_commitment_queue(ctx: Context<InitCommitmentQueue>) -> Result<()> {
+        ctx.accounts.commitment_queue.core_state = ctx.accounts.core_state.key();
+        ctx.accounts.commitment_queue.commitments = Vec::new();
+        Ok(())
+    }
+
+    pub fn push_commitments(ctx: Context<PushCommitments>, args: PushCommitmentsArgs) -> Result<()> {
+        let commitments = &mut ctx.accounts.commitment_queue.commitments;
+
+        if commitments.len() + args.commitments.len() > MAX_COMMITMENTS {
+            return Err(ErrorCode::CommitmentQueueFull.into());
+        }
+        commitments.extend(args.commitments);
+        Ok(())
+    }
+
+    pub fn withdraw_rent_sponsor(ctx: Context<WithdrawRentSponsor>, args: WithdrawArgs) -> Result<()> {
+        let core_state = &ctx.accounts.core_state;
+        let rent_sponsor = &ctx.accounts.rent_sponsor;
         let admin = &ctx.accounts.admin;
+
+        let rent_sponsor_seeds = [
+            RENT_SPONSOR_SEED.as_bytes(),
+            core_state.house_id.as_ref(),
+            &[*ctx.bumps.get("rent_sponsor").unwrap()],
+        ];
+        anchor_lang::solana_program::program::invoke_signed(
+            &anchor_lang::solana_program::system_instruction::transfer(
+                &rent_sponsor.key(),
+                &admin.key(),
+                args.amount,
+            ),
+            &[
+                rent_sponsor.to_account_info(),
+                admin.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+            ],
+            &[&rent_sponsor_seeds],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn deposit(ctx: Context<Deposit>, args: DepositArgs) -> Result<()> {
+        let treasurer = &ctx.accounts.treasurer;
         let vault_authority = &ctx.accounts.vault_authority;
         let token_mint = &ctx.accounts.token_mint;
-        let admin_token_account = &ctx.accounts.admin_token_account;
+        let treasurer_token_account = &ctx.accounts.treasurer_token_account;
         let vault_token_account = &ctx.accounts.vault_token_account;
         let token_program = &ctx.accounts.token_program;
         let system_program = &ctx.accounts.system_program;
@@ -64,290 +592,365 @@ pub mod coin_flip {
         let is_native = token_mint.key() == spl_token::native_mint::id();
 
         if !is_native {
-            utils::assert_is_ata(&admin_token_account, &admin.key(), &token_mint.key())?;
+            utils::assert_is_ata(treasurer_token_account, &treasurer.key(), &token_mint.key())?;
             anchor_lang::solana_program::program::invoke(
                 &spl_token::instruction::transfer(
                     &token_program.key(),
-                    &admin_token_account.key(),
+                    &treasurer_token_account.key(),
                     &vault_token_account.key(),
-                    &admin.key(),
+                    &treasurer.key(),
                     &[],
                     args.amount,
                 )?,
                 &[
                     vault_token_account.to_account_info(),
-                    admin_token_account.to_account_info(),
+                    treasurer_token_account.to_account_info(),
                     token_program.to_account_info(),
-                    admin.to_account_info(),
+                    treasurer.to_account_info(),
                 ],
             )?;
         } else {
-            utils::assert_keys_equal(admin_token_account.key(), admin.key())?;
+            utils::assert_keys_equal(treasurer_token_account.key(), treasurer.key())?;
             utils::assert_keys_equal(vault_token_account.key(), vault_authority.key())?;
             anchor_lang::solana_program::program::invoke(
                 &anchor_lang::solana_program::system_instruction::transfer(
-                    &admin_token_account.key(),
+                    &treasurer_token_account.key(),
                     &vault_token_account.key(),
                     args.amount,
                 ),
                 &[
                     vault_token_account.to_account_info(),
-                    admin_token_account.to_account_info(),
+                    treasurer_token_account.to_account_info(),
                     system_program.to_account_info(),
                 ],
             )?;
         }
 
+        emit!(Deposited {
+            user: treasurer.key(),
+            token_mint: token_mint.key(),
+            flip_counter: ctx.accounts.core_state.flip_counter,
+            amount: args.amount,
+        });
+
         Ok(())
     }
 
     pub fn withdraw(ctx: Context<Withdraw>, args: WithdrawArgs) -> Result<()> {
         let core_state = &ctx.accounts.core_state;
-        let admin = &ctx.accounts.admin;
+        let destination = &ctx.accounts.destination;
         let vault_authority = &ctx.accounts.vault_authority;
         let token_mint = &ctx.accounts.token_mint;
-        let admin_token_account = &ctx.accounts.admin_token_account;
+        let destination_token_account = &ctx.accounts.destination_token_account;
         let vault_token_account = &ctx.accounts.vault_token_account;
         let token_program = &ctx.accounts.token_program;
         let system_program = &ctx.accounts.system_program;
 
         let is_native = token_mint.key() == spl_token::native_mint::id();
+        core_state.assert_withdraw_destination(&destination.key())?;
+
+        // once LPs share the vault, the house takes part through LP shares only
+        if ctx.accounts.mint_ledger.lp_mint != Pubkey::default() {
+            return Err(ErrorCode::LiquidityPoolOpen.into());
+        }
+
+        // funds owed to pending bets stay in the vault
+        let vault_balance = utils::vault_balance(vault_token_account, is_native)?;
+        utils::assert_solvent(vault_balance, ctx.accounts.mint_ledger.pending_liability, args.amount)?;
 
         let vault_auth_seeds = [
             VAULT_AUTH_SEED.as_bytes(),
-            core_state.admin.as_ref(),
+            core_state.house_id.as_ref(),
             &[core_state.vault_auth_nonce],
         ];
 
         if !is_native {
-            utils::assert_is_ata(&admin_token_account, &admin.key(), &token_mint.key())?;
+            utils::assert_is_ata(destination_token_account, &destination.key(), &token_mint.key())?;
             anchor_lang::solana_program::program::invoke_signed(
                 &spl_token::instruction::transfer(
                     &token_program.key(),
                     &vault_token_account.key(),
-                    &admin_token_account.key(),
+                    &destination_token_account.key(),
                     &vault_authority.key(),
                     &[],
                     args.amount,
                 )?,
                 &[
                     vault_token_account.to_account_info(),
-                    admin_token_account.to_account_info(),
+                    destination_token_account.to_account_info(),
                     token_program.to_account_info(),
                     vault_authority.to_account_info(),
                 ],
                 &[&vault_auth_seeds],
             )?;
         } else {
-            utils::assert_keys_equal(admin_token_account.key(), admin.key())?;
+            utils::assert_keys_equal(destination_token_account.key(), destination.key())?;
             utils::assert_keys_equal(vault_token_account.key(), vault_authority.key())?;
             anchor_lang::solana_program::program::invoke_signed(
                 &anchor_lang::solana_program::system_instruction::transfer(
                     &vault_token_account.key(),
-                    &admin_token_account.key(),
+                    &destination_token_account.key(),
                     args.amount,
                 ),
                 &[
                     vault_token_account.to_account_info(),
-                    admin_token_account.to_account_info(),
+                    destination_token_account.to_account_info(),
                     system_program.to_account_info(),
-                    admin.to_account_info(),
                 ],
                 &[&vault_auth_seeds],
             )?;
         }
 
+        emit!(Withdrawn {
+            user: ctx.accounts.treasurer.key(),
+            token_mint: token_mint.key(),
+            flip_counter: core_state.flip_counter,
+            destination: destination.key(),
+            amount: args.amount,
+        });
+
         Ok(())
     }
 
-    pub fn bet_directly(ctx: Context<BetDirectly>, args: BetDirectlyArgs) -> Result<()> {
-        ctx.accounts.core_state.flip_counter += 1;
+    /// Moves a mint's funds out of a vault seeded by the admin key, from before house_id
+    /// seeds, into this house's vault. The mint has to be registered with the house first.
+    /// Bets still pending in the legacy vault are refunded from here by refund_legacy_bet.
+    pub fn migrate_vault(ctx: Context<MigrateVault>) -> Result<()> {
+        let admin = &ctx.accounts.admin;
+        let token_mint = &ctx.accounts.token_mint;
+        let legacy_vault_authority = &ctx.accounts.legacy_vault_authority;
+        let legacy_vault_token_account = &ctx.accounts.legacy_vault_token_account;
+
+        utils::assert_legacy_core_state(&ctx.accounts.legacy_core_state, &admin.key())?;
+        let legacy_vault_auth_seeds = [
+            VAULT_AUTH_SEED.as_bytes(),
+            admin.key.as_ref(),
+            &[*ctx.bumps.get("legacy_vault_authority").unwrap()],
+        ];
+
+        let is_native = token_mint.key() == spl_token::native_mint::id();
+        let legacy_vault = if is_native {
+            legacy_vault_authority.to_account_info()
+        } else {
+            legacy_vault_token_account.to_account_info()
+        };
+
+        let amount = utils::vault_balance(&legacy_vault, is_native)?;
+        if amount > 0 {
+            utils::transfer_from_vault(
+                &legacy_vault,
+                &ctx.accounts.vault_token_account,
+                legacy_vault_authority,
+                &ctx.accounts.token_program,
+                &ctx.accounts.system_program,
+                &legacy_vault_auth_seeds,
+                amount,
+                is_native,
+            )?;
+        }
+        ctx.accounts.mint_ledger.legacy_core_state = ctx.accounts.legacy_core_state.key();
+
+        Ok(())
+    }
 
+    /// Refunds a bet placed before house_id seeds that was never settled. Its stake was
+    /// moved into this house's vault by migrate_vault. The legacy BetState has no record
+    /// of its fee, so only the amount is returned.
+    pub fn refund_legacy_bet(ctx: Context<RefundLegacyBet>) -> Result<()> {
         let core_state = &ctx.accounts.core_state;
         let user = &ctx.accounts.user;
-        let vault_authority = &ctx.accounts.vault_authority;
         let token_mint = &ctx.accounts.token_mint;
         let user_token_account = &ctx.accounts.user_token_account;
-        let vault_token_account = &ctx.accounts.vault_token_account;
-        let token_program = &ctx.accounts.token_program;
-        let system_program = &ctx.accounts.system_program;
-        let allowed_amounts = &ctx.accounts.allowed_bets.amounts;
+        let legacy_bet_state = &ctx.accounts.legacy_bet_state;
 
-        utils::assert_allowed_amount(allowed_amounts, args.amount)?;
-        utils::assert_keys_equal(ctx.accounts.allowed_bets.token_mint.key(), token_mint.key())?;
+        let (amount, flip_counter) = utils::load_legacy_bet_state(
+            legacy_bet_state,
+            &ctx.accounts.mint_ledger.legacy_core_state,
+            &user.key(),
+            &token_mint.key(),
+        )?;
 
         let is_native = token_mint.key() == spl_token::native_mint::id();
-        let fee = args.amount * (core_state.fee_percent as u64) / 10000;
-
         if !is_native {
-            utils::assert_is_ata(&user_token_account, &user.key(), &token_mint.key())?;
-            anchor_lang::solana_program::program::invoke(
-                &spl_token::instruction::transfer(
-                    &token_program.key(),
-                    &user_token_account.key(),
-                    &vault_token_account.key(),
-                    &user.key(),
-                    &[],
-                    args.amount + fee,
-                )?,
-                &[
-                    vault_token_account.to_account_info(),
-                    user_token_account.to_account_info(),
-                    token_program.to_account_info(),
-                    user.to_account_info(),
-                ],
-            )?;
+            utils::assert_is_ata(user_token_account, &user.key(), &token_mint.key())?;
         } else {
             utils::assert_keys_equal(user_token_account.key(), user.key())?;
-            utils::assert_keys_equal(vault_token_account.key(), vault_authority.key())?;
-            anchor_lang::solana_program::program::invoke(
-                &anchor_lang::solana_program::system_instruction::transfer(
-                    &user_token_account.key(),
-                    &vault_token_account.key(),
-                    args.amount + fee,
-                ),
-                &[
-                    vault_token_account.to_account_info(),
-                    user_token_account.to_account_info(),
-                    system_program.to_account_info(),
-                ],
-            )?;
         }
 
-        let clock = (Clock::get()?).unix_timestamp as u64;
-        let hash = calc_hash(clock, core_state.flip_counter);
-        let hash_remain = hash % 10000;
+        let vault_auth_seeds = [
+            VAULT_AUTH_SEED.as_bytes(),
+            core_state.house_id.as_ref(),
+            &[core_state.vault_auth_nonce],
+        ];
+        utils::transfer_from_vault(
+            &ctx.accounts.vault_token_account,
+            user_token_account,
+            &ctx.accounts.vault_authority,
+            &ctx.accounts.token_program,
+            &ctx.accounts.system_program,
+            &vault_auth_seeds,
+            amount,
+            is_native,
+        )?;
+        // legacy bets were always paid for by the user
+        utils::close_account(legacy_bet_state, &user.to_account_info())?;
+
+        emit!(BetRefunded {
+            bet_state: legacy_bet_state.key(),
+            user: user.key(),
+            token_mint: token_mint.key(),
+            flip_counter,
+            amount,
+            fee: 0,
+        });
+
+        Ok(())
+    }
+
+    pub fn init_lp_pool(ctx: Context<InitLpPool>) -> Result<()> {
+        let core_state = &ctx.accounts.core_state;
+        let is_native = ctx.accounts.token_mint.key() == spl_token::native_mint::id();
 
-        let is_win = (args.bet_side && hash_remain < core_state.win_ratio as u64) || 
-            (!args.bet_side && hash_remain >= 10000 - core_state.win_ratio as u64);
+        // what the admin has in the vault becomes the first LP position
+        let vault_balance = utils::vault_balance(&ctx.accounts.vault_token_account, is_native)?;
+        let pool_value = vault_balance.saturating_sub(ctx.accounts.mint_ledger.pending_liability);
+        let shares = utils::calc_lp_shares(pool_value, 0, 0)?;
 
-        if is_win {
+        if shares > 0 {
             let vault_auth_seeds = [
                 VAULT_AUTH_SEED.as_bytes(),
-                core_state.admin.as_ref(),
+                core_state.house_id.as_ref(),
                 &[core_state.vault_auth_nonce],
             ];
-    
-            if !is_native {
-                utils::assert_is_ata(&user_token_account, &user.key(), &token_mint.key())?;
-                anchor_lang::solana_program::program::invoke_signed(
-                    &spl_token::instruction::transfer(
-                        &token_program.key(),
-                        &vault_token_account.key(),
-                        &user_token_account.key(),
-                        &vault_authority.key(),
-                        &[],
-                        2 * args.amount,
-                    )?,
-                    &[
-                        vault_token_account.to_account_info(),
-                        user_token_account.to_account_info(),
-                        token_program.to_account_info(),
-                        vault_authority.to_account_info(),
-                    ],
+            token::mint_to(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    MintTo {
+                        mint: ctx.accounts.lp_mint.to_account_info(),
+                        to: ctx.accounts.admin_lp_account.to_account_info(),
+                        authority: ctx.accounts.vault_authority.to_account_info(),
+                    },
                     &[&vault_auth_seeds],
-                )?;
-            } else {
-                utils::assert_keys_equal(user_token_account.key(), user.key())?;
-                utils::assert_keys_equal(vault_token_account.key(), vault_authority.key())?;
-                anchor_lang::solana_program::program::invoke_signed(
-                    &anchor_lang::solana_program::system_instruction::transfer(
-                        &vault_token_account.key(),
-                        &user_token_account.key(),
-                        2 * args.amount,
-                    ),
-                    &[
-                        vault_token_account.to_account_info(),
-                        user_token_account.to_account_info(),
-                        system_program.to_account_info(),
-                        user.to_account_info(),
-                    ],
-                    &[&vault_auth_seeds],
-                )?;
-            }
-            msg!("Congratulations, You won!");
-        }
-        else {
-            msg!("Sorry, You lost!");
+                ),
+                shares,
+            )?;
         }
 
+        ctx.accounts.mint_ledger.lp_mint = ctx.accounts.lp_mint.key();
         Ok(())
     }
 
-    pub fn bet(ctx: Context<Bet>, args: BetArgs) -> Result<()> {
-        ctx.accounts.core_state.flip_counter += 1;
-
+    pub fn add_liquidity(ctx: Context<AddLiquidity>, args: DepositArgs) -> Result<()> {
         let core_state = &ctx.accounts.core_state;
-        let user = &ctx.accounts.user;
-        let vault_authority = &ctx.accounts.vault_authority;
+        let provider = &ctx.accounts.provider;
         let token_mint = &ctx.accounts.token_mint;
-        let user_token_account = &ctx.accounts.user_token_account;
+        let provider_token_account = &ctx.accounts.provider_token_account;
         let vault_token_account = &ctx.accounts.vault_token_account;
-        let token_program = &ctx.accounts.token_program;
-        let system_program = &ctx.accounts.system_program;
+        let lp_mint = &ctx.accounts.lp_mint;
 
-        let allowed_amounts = &ctx.accounts.allowed_bets.amounts;
+        let is_native = token_mint.key() == spl_token::native_mint::id();
+        if !is_native {
+            utils::assert_is_ata(provider_token_account, &provider.key(), &token_mint.key())?;
+        } else {
+            utils::assert_keys_equal(provider_token_account.key(), provider.key())?;
+        }
 
-        utils::assert_allowed_amount(allowed_amounts, args.amount)?;
-        utils::assert_keys_equal(ctx.accounts.allowed_bets.token_mint.key(), token_mint.key())?;
+        // shares are priced on the gross vault balance before this deposit, as if every
+        // pending bet loses, so a deposit never buys in below what the pool is worth
+        let vault_balance = utils::vault_balance(vault_token_account, is_native)?;
+        let shares = utils::calc_lp_shares(args.amount, vault_balance, lp_mint.supply)?;
+        if shares == 0 {
+            return Err(ErrorCode::ZeroShares.into());
+        }
 
-        let is_native = token_mint.key() == spl_token::native_mint::id();
-        let fee = args.amount * (core_state.fee_percent as u64) / 10000;
+        utils::transfer_to_vault(
+            provider_token_account,
+            vault_token_account,
+            &provider.to_account_info(),
+            &ctx.accounts.token_program,
+            &ctx.accounts.system_program,
+            args.amount,
+            is_native,
+        )?;
+
+        let vault_auth_seeds = [
+            VAULT_AUTH_SEED.as_bytes(),
+            core_state.house_id.as_ref(),
+            &[core_state.vault_auth_nonce],
+        ];
+        token::mint_to(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                MintTo {
+                    mint: lp_mint.to_account_info(),
+                    to: ctx.accounts.provider_lp_account.to_account_info(),
+                    authority: ctx.accounts.vault_authority.to_account_info(),
+                },
+                &[&vault_auth_seeds],
+            ),
+            shares,
+        )?;
+
+        Ok(())
+    }
+
+    pub fn remove_liquidity(ctx: Context<RemoveLiquidity>, args: RemoveLiquidityArgs) -> Result<()> {
+        let core_state = &ctx.accounts.core_state;
+        let provider = &ctx.accounts.provider;
+        let token_mint = &ctx.accounts.token_mint;
+        let provider_token_account = &ctx.accounts.provider_token_account;
+        let vault_token_account = &ctx.accounts.vault_token_account;
+        let lp_mint = &ctx.accounts.lp_mint;
 
+        let is_native = token_mint.key() == spl_token::native_mint::id();
         if !is_native {
-            utils::assert_is_ata(&user_token_account, &user.key(), &token_mint.key())?;
-            anchor_lang::solana_program::program::invoke(
-                &spl_token::instruction::transfer(
-                    &token_program.key(),
-                    &user_token_account.key(),
-                    &vault_token_account.key(),
-                    &user.key(),
-                    &[],
-                    args.amount + fee,
-                )?,
-                &[
-                    vault_token_account.to_account_info(),
-                    user_token_account.to_account_info(),
-                    token_program.to_account_info(),
-                    user.to_account_info(),
-                ],
-            )?;
+            utils::assert_is_ata(provider_token_account, &provider.key(), &token_mint.key())?;
         } else {
-            utils::assert_keys_equal(user_token_account.key(), user.key())?;
-            utils::assert_keys_equal(vault_token_account.key(), vault_authority.key())?;
-            anchor_lang::solana_program::program::invoke(
-                &anchor_lang::solana_program::system_instruction::transfer(
-                    &user_token_account.key(),
-                    &vault_token_account.key(),
-                    args.amount + fee,
-                ),
-                &[
-                    vault_token_account.to_account_info(),
-                    user_token_account.to_account_info(),
-                    system_program.to_account_info(),
-                ],
-            )?;
+            utils::assert_keys_equal(provider_token_account.key(), provider.key())?;
         }
 
-        ctx.accounts.bet_state.core_state = core_state.key();
-        ctx.accounts.bet_state.bet_state_nonce = args.bet_state_nonce;
-        ctx.accounts.bet_state.user = user.key();
-        ctx.accounts.bet_state.token_mint = token_mint.key();
-        ctx.accounts.bet_state.amount = args.amount;
-        ctx.accounts.bet_state.bet_side = args.bet_side;
-        ctx.accounts.bet_state.flip_counter = args.flip_counter;
-        ctx.accounts.bet_state.approved = true;
+        let vault_balance = utils::vault_balance(vault_token_account, is_native)?;
+        let pending_liability = ctx.accounts.mint_ledger.pending_liability;
+        let pool_value = vault_balance.saturating_sub(pending_liability);
+        let amount = utils::calc_lp_redemption(args.shares, pool_value, lp_mint.supply)?;
+        utils::assert_solvent(vault_balance, pending_liability, amount)?;
+
+        token::burn(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Burn {
+                    mint: lp_mint.to_account_info(),
+                    to: ctx.accounts.provider_lp_account.to_account_info(),
+                    authority: provider.to_account_info(),
+                },
+            ),
+            args.shares,
+        )?;
+
+        let vault_auth_seeds = [
+            VAULT_AUTH_SEED.as_bytes(),
+            core_state.house_id.as_ref(),
+            &[core_state.vault_auth_nonce],
+        ];
+        utils::transfer_from_vault(
+            vault_token_account,
+            provider_token_account,
+            &ctx.accounts.vault_authority,
+            &ctx.accounts.token_program,
+            &ctx.accounts.system_program,
+            &vault_auth_seeds,
+            amount,
+            is_native,
+        )?;
 
         Ok(())
     }
 
-    pub fn bet_return(ctx: Context<BetReturn>) -> Result<()> {
-        ctx.accounts.bet_state.approved = false;
+    pub fn bet_directly(ctx: Context<BetDirectly>, args: BetDirectlyArgs) -> Result<()> {
+        utils::assert_last_top_level_instruction(&ctx.accounts.instructions)?;
+        ctx.accounts.core_state.flip_counter += 1;
 
-        let admin = &ctx.accounts.admin;
-        let executer = &ctx.accounts.executer;
         let core_state = &ctx.accounts.core_state;
-        let bet_state = &ctx.accounts.bet_state;
         let user = &ctx.accounts.user;
         let vault_authority = &ctx.accounts.vault_authority;
         let token_mint = &ctx.accounts.token_mint;
@@ -355,22 +958,82 @@ pub mod coin_flip {
         let vault_token_account = &ctx.accounts.vault_token_account;
         let token_program = &ctx.accounts.token_program;
         let system_program = &ctx.accounts.system_program;
-
-        utils::assert_keys_equal(executer.key(), core_state.executer.key())?;
+        utils::assert_allowed_amount(&ctx.accounts.allowed_bets, args.amount)?;
+        utils::assert_keys_equal(ctx.accounts.allowed_bets.token_mint.key(), token_mint.key())?;
 
         let is_native = token_mint.key() == spl_token::native_mint::id();
-        
-        let clock = (Clock::get()?).unix_timestamp as u64;
-        let hash = calc_hash(clock, core_state.flip_counter);
-        let hash_remain = hash % 10000;
+        let (fee, loyalty_points) = ctx.accounts.player_stats.charge_fee(args.amount, core_state)?;
+
+        if !is_native {
+            utils::assert_is_ata(&user_token_account, &user.key(), &token_mint.key())?;
+        } else {
+            utils::assert_keys_equal(user_token_account.key(), user.key())?;
+            utils::assert_keys_equal(vault_token_account.key(), vault_authority.key())?;
+        }
+        // the amount backs the bankroll, the fee goes to the fee vault
+        utils::transfer_to_vault(
+            user_token_account,
+            vault_token_account,
+            &user.to_account_info(),
+            token_program,
+            system_program,
+            args.amount,
+            is_native,
+        )?;
+        if fee > 0 {
+            utils::transfer_to_vault(
+                user_token_account,
+                &ctx.accounts.fee_vault,
+                &user.to_account_info(),
+                token_program,
+                system_program,
+                fee,
+                is_native,
+            )?;
+        }
 
-        let is_win = (bet_state.bet_side && hash_remain < core_state.win_ratio as u64) || 
-            (!bet_state.bet_side && hash_remain >= 10000 - core_state.win_ratio as u64);
+        let payout = utils::calc_payout(args.amount, core_state.payout_multiplier_bps)?;
+        let vault_balance = utils::vault_balance(vault_token_account, is_native)?;
+        utils::assert_solvent(vault_balance, ctx.accounts.mint_ledger.pending_liability, payout)?;
+
+        let allowed_bets = &ctx.accounts.allowed_bets;
+        utils::assert_max_payout(vault_balance, ctx.accounts.mint_ledger.pending_liability, payout, allowed_bets.max_payout_bps)?;
+        ctx.accounts.user_exposure.assert_within(payout, allowed_bets.max_user_liability)?;
+        ctx.accounts.user_exposure.user = user.key();
+        ctx.accounts.user_exposure.token_mint = token_mint.key();
+        ctx.accounts.mint_ledger.record_volume(Clock::get()?.slot, args.amount, allowed_bets)?;
+
+        let outcome = randomness::resolve_direct(core_state.randomness_mode, &ctx.accounts.slot_hashes, core_state.flip_counter)?;
+        let is_win = randomness::is_win(args.bet_side, outcome.roll, core_state.win_ratio);
+
+        ctx.accounts.mint_ledger.record_direct_bet(args.amount, fee, if is_win { payout } else { 0 })?;
+        ctx.accounts.player_stats.user = user.key();
+        ctx.accounts.player_stats.record(&token_mint.key(), args.amount, if is_win { payout } else { 0 }, Clock::get()?.slot)?;
+        ctx.accounts.player_stats.credit_loyalty_points(loyalty_points)?;
+        let (referrer, referral_reward) = calc_referral_reward(
+            core_state,
+            &user.key(),
+            &token_mint.key(),
+            args.referrer,
+            ctx.remaining_accounts,
+            fee,
+        )?;
+        if referral_reward > 0 {
+            credit_referral_reward(
+                &ctx.remaining_accounts[1],
+                &core_state.key(),
+                &referrer,
+                &token_mint.key(),
+                referral_reward,
+                ctx.program_id,
+            )?;
+            ctx.accounts.mint_ledger.record_referral_reward(referral_reward)?;
+        }
 
         if is_win {
             let vault_auth_seeds = [
                 VAULT_AUTH_SEED.as_bytes(),
-                core_state.admin.as_ref(),
+                core_state.house_id.as_ref(),
                 &[core_state.vault_auth_nonce],
             ];
     
@@ -383,7 +1046,7 @@ pub mod coin_flip {
                         &user_token_account.key(),
                         &vault_authority.key(),
                         &[],
-                        2 * bet_state.amount,
+                        payout,
                     )?,
                     &[
                         vault_token_account.to_account_info(),
@@ -400,7 +1063,7 @@ pub mod coin_flip {
                     &anchor_lang::solana_program::system_instruction::transfer(
                         &vault_token_account.key(),
                         &user_token_account.key(),
-                        2 * bet_state.amount,
+                        payout,
                     ),
                     &[
                         vault_token_account.to_account_info(),
@@ -417,37 +1080,598 @@ pub mod coin_flip {
             msg!("Sorry, You lost!");
         }
 
-        // close pda
-        let bet_state_acc = &ctx.accounts.bet_state.to_account_info();
-        let bet_state_data = &mut bet_state_acc.try_borrow_mut_data()?;
-        let cur_lamp = bet_state_acc.lamports();
+        emit!(BetSettled {
+            bet_state: Pubkey::default(),
+            user: user.key(),
+            token_mint: token_mint.key(),
+            flip_counter: core_state.flip_counter,
+            game: Game::CoinFlip,
+            randomness_mode: core_state.randomness_mode,
+            client_seed: [0u8; 32],
+            entropy: outcome.entropy,
+            roll: outcome.roll,
+            secret: outcome.secret,
+            slot_hash: outcome.slot_hash,
+            signature: outcome.signature,
+            is_win,
+            amount: args.amount,
+            fee,
+            paid_out: if is_win { payout } else { 0 },
+        });
 
-        **bet_state_acc.lamports.borrow_mut() = 0;
-        sol_memset(&mut *bet_state_data, 0, 1);
+        Ok(())
+    }
 
-        **admin.lamports.borrow_mut() = admin
-            .lamports()
-            .checked_add(cur_lamp)
-            .ok_or(ErrorCode::NumericalOverflow)?;
+    pub fn bet(ctx: Context<Bet>, args: BetArgs) -> Result<()> {
+        let multiplier_bps = ctx.accounts.core_state.payout_multiplier_bps;
+        let rent_sponsor_bump = *ctx.bumps.get("rent_sponsor").unwrap();
+
+        ctx.accounts.bet_state.bet_side = args.bet_side;
+        place_bet(ctx.accounts, ctx.remaining_accounts, ctx.program_id, PlaceBetParams {
+            rent_sponsor_bump,
+            game: Game::CoinFlip,
+            amount: args.amount,
+            payout_multiplier_bps: multiplier_bps,
+            flip_counter: args.flip_counter,
+            bet_state_nonce: args.bet_state_nonce,
+            client_seed: args.client_seed,
+            referrer: args.referrer,
+        })?;
+        Ok(())
+    }
+
+    pub fn bet_dice(ctx: Context<Bet>, args: BetDiceArgs) -> Result<()> {
+        let multiplier_bps = randomness::dice_multiplier_bps(args.target, args.roll_over, ctx.accounts.core_state.dice_edge_bps)?;
+        let rent_sponsor_bump = *ctx.bumps.get("rent_sponsor").unwrap();
+
+        ctx.accounts.bet_state.dice_target = args.target;
+        ctx.accounts.bet_state.dice_roll_over = args.roll_over;
+        place_bet(ctx.accounts, ctx.remaining_accounts, ctx.program_id, PlaceBetParams {
+            rent_sponsor_bump,
+            game: Game::Dice,
+            amount: args.amount,
+            payout_multiplier_bps: multiplier_bps,
+            flip_counter: args.flip_counter,
+            bet_state_nonce: args.bet_state_nonce,
+            client_seed: args.client_seed,
+            referrer: args.referrer,
+        })?;
+        Ok(())
+    }
+
+    pub fn bet_return(ctx: Context<BetReturn>, args: BetReturnArgs) -> Result<()> {
+        let bet_state = &ctx.accounts.bet_state;
+
+        // oracle bets settle through bet_return_vrf
+        if bet_state.randomness_mode == RandomnessMode::Ed25519Oracle || bet_state.game != Game::CoinFlip {
+            return Err(ErrorCode::WrongSettlement.into());
+        }
+        let outcome = randomness::resolve_settlement(
+            bet_state,
+            &bet_state.key(),
+            &ctx.accounts.slot_hashes,
+            &ctx.accounts.instructions,
+            args.secret,
+        )?;
+        let is_win = bet_state.is_win(outcome.roll);
+
+        settle_bet(ctx.accounts, ctx.remaining_accounts, ctx.program_id, outcome, is_win)
+    }
+
+    /// Settles an Ed25519Oracle bet. The signature is not a VRF proof, so the oracle is
+    /// trusted not to grind the outcome, see RandomnessMode::Ed25519Oracle.
+    pub fn bet_return_vrf(ctx: Context<BetReturn>) -> Result<()> {
+        let bet_state = &ctx.accounts.bet_state;
+
+        // settles either game, settle_bet picks the rule from bet_state.game
+        if bet_state.randomness_mode != RandomnessMode::Ed25519Oracle {
+            return Err(ErrorCode::WrongSettlement.into());
+        }
+        let outcome = randomness::resolve_settlement(
+            bet_state,
+            &bet_state.key(),
+            &ctx.accounts.slot_hashes,
+            &ctx.accounts.instructions,
+            None,
+        )?;
+        let is_win = bet_state.is_win(outcome.roll);
+
+        settle_bet(ctx.accounts, ctx.remaining_accounts, ctx.program_id, outcome, is_win)
+    }
+
+    pub fn bet_dice_return(ctx: Context<BetReturn>, args: BetReturnArgs) -> Result<()> {
+        let bet_state = &ctx.accounts.bet_state;
+
+        if bet_state.randomness_mode == RandomnessMode::Ed25519Oracle || bet_state.game != Game::Dice {
+            return Err(ErrorCode::WrongSettlement.into());
+        }
+        let outcome = randomness::resolve_settlement(
+            bet_state,
+            &bet_state.key(),
+            &ctx.accounts.slot_hashes,
+            &ctx.accounts.instructions,
+            args.secret,
+        )?;
+        let is_win = bet_state.is_win(outcome.roll);
+
+        settle_bet(ctx.accounts, ctx.remaining_accounts, ctx.program_id, outcome, is_win)
+    }
+
+    /// Pays out a CommitReveal bet the executer never revealed. The executer knows every
+    /// outcome in advance, so withholding the reveal of a winning bet must not help it.
+    pub fn settle_expired_bet(ctx: Context<BetReturn>) -> Result<()> {
+        let bet_state = &ctx.accounts.bet_state;
+
+        if bet_state.randomness_mode != RandomnessMode::CommitReveal {
+            return Err(ErrorCode::WrongSettlement.into());
+        }
+        let now = Clock::get()?.unix_timestamp;
+        if now < bet_state.expires_at {
+            return Err(ErrorCode::BetNotExpired.into());
+        }
+
+        settle_bet(ctx.accounts, ctx.remaining_accounts, ctx.program_id, randomness::Outcome::default(), true)
+    }
+
+    pub fn refund_expired_bet(ctx: Context<RefundExpiredBet>) -> Result<()> {
+        let core_state = &ctx.accounts.core_state;
+        let bet_state = &ctx.accounts.bet_state;
+        let user = &ctx.accounts.user;
+        let token_mint = &ctx.accounts.token_mint;
+        let user_token_account = &ctx.accounts.user_token_account;
+
+        let now = Clock::get()?.unix_timestamp;
+        if now < bet_state.expires_at {
+            return Err(ErrorCode::BetNotExpired.into());
+        }
+        match bet_state.randomness_mode {
+            // expired CommitReveal bets are paid out by settle_expired_bet
+            RandomnessMode::CommitReveal => return Err(ErrorCode::WrongSettlement.into()),
+            // anyone can settle a SlotHash bet while its slot hash is in the sysvar, so a
+            // refund only comes once the outcome can no longer be computed
+            RandomnessMode::SlotHash => {
+                if utils::slot_hash_at(&ctx.accounts.slot_hashes, bet_state.placed_slot).is_ok() {
+                    return Err(ErrorCode::SlotHashAvailable.into());
+                }
+            }
+            RandomnessMode::LegacyHash | RandomnessMode::Ed25519Oracle => {}
+        }
+
+        let is_native = token_mint.key() == spl_token::native_mint::id();
+        if !is_native {
+            utils::assert_is_ata(user_token_account, &user.key(), &token_mint.key())?;
+        } else {
+            utils::assert_keys_equal(user_token_account.key(), user.key())?;
+        }
+
+        let vault_auth_seeds = [
+            VAULT_AUTH_SEED.as_bytes(),
+            core_state.house_id.as_ref(),
+            &[core_state.vault_auth_nonce],
+        ];
+        utils::transfer_from_vault(
+            &ctx.accounts.vault_token_account,
+            user_token_account,
+            &ctx.accounts.vault_authority,
+            &ctx.accounts.token_program,
+            &ctx.accounts.system_program,
+            &vault_auth_seeds,
+            bet_state.amount,
+            is_native,
+        )?;
+        if bet_state.fee > 0 {
+            utils::transfer_from_fee_vault(
+                &ctx.accounts.fee_vault,
+                user_token_account,
+                &ctx.accounts.vault_authority,
+                &ctx.accounts.token_program,
+                &ctx.accounts.system_program,
+                core_state,
+                ctx.accounts.mint_ledger.fee_vault_nonce,
+                bet_state.fee,
+                is_native,
+            )?;
+        }
+
+        let (amount, fee, payout) = (bet_state.amount, bet_state.fee, bet_state.payout);
+        ctx.accounts.mint_ledger.record_refund(amount, fee, payout)?;
+        ctx.accounts.user_exposure.release(payout)?;
+        // the bet's loyalty points were never credited
+        if ctx.accounts.bet_state.free_fee_bet {
+            ctx.accounts.player_stats.restore_free_fee_bet()?;
+        }
+
+        emit!(BetRefunded {
+            bet_state: ctx.accounts.bet_state.key(),
+            user: user.key(),
+            token_mint: token_mint.key(),
+            flip_counter: ctx.accounts.bet_state.flip_counter,
+            amount,
+            fee,
+        });
 
-        
         Ok(())
     }
 }
 
-pub fn calc_hash(clock: u64, flip_counter: u64) -> u64 {
-    let mut hasher = DefaultHasher::new();
+/// The instruction args bet and bet_dice share, resolved for place_bet.
+pub struct PlaceBetParams {
+    pub rent_sponsor_bump: u8,
+    pub game: Game,
+    pub amount: u64,
+    pub payout_multiplier_bps: u32,
+    pub flip_counter: u64,
+    pub bet_state_nonce: u8,
+    pub client_seed: [u8; 32],
+    pub referrer: Option<Pubkey>,
+}
+
+/// Takes the amount and fee into the vault and records the pending bet. Shared by
+/// bet and bet_dice, which fill in their game fields first.
+pub fn place_bet(
+    accounts: &mut Bet,
+    remaining_accounts: &[AccountInfo],
+    program_id: &Pubkey,
+    params: PlaceBetParams,
+) -> Result<()> {
+    let PlaceBetParams {
+        rent_sponsor_bump,
+        game,
+        amount,
+        payout_multiplier_bps,
+        flip_counter,
+        bet_state_nonce,
+        client_seed,
+        referrer,
+    } = params;
+    accounts.core_state.flip_counter += 1;
+
+    let core_state = &accounts.core_state;
+    let user = &accounts.user;
+    let vault_authority = &accounts.vault_authority;
+    let token_mint = &accounts.token_mint;
+    let user_token_account = &accounts.user_token_account;
+    let vault_token_account = &accounts.vault_token_account;
+    let token_program = &accounts.token_program;
+    let system_program = &accounts.system_program;
+
+    utils::assert_allowed_amount(&accounts.allowed_bets, amount)?;
+    let payout = utils::calc_payout(amount, payout_multiplier_bps)?;
+    utils::assert_keys_equal(accounts.allowed_bets.token_mint.key(), token_mint.key())?;
+
+    // take the oldest commitment published by the executer before this bet existed
+    let mut commitment = [0u8; 32];
+    if core_state.randomness_mode == RandomnessMode::CommitReveal {
+        // owned by this program with this core_state, so it is the house's queue
+        let mut commitment_queue: Account<CommitmentQueue> = Account::try_from(&accounts.commitment_queue)?;
+        if commitment_queue.core_state != core_state.key() {
+            return Err(ErrorCode::InvalidCoreState.into());
+        }
+        if commitment_queue.commitments.is_empty() {
+            return Err(ErrorCode::EmptyCommitmentQueue.into());
+        }
+        commitment = commitment_queue.commitments.remove(0);
+        commitment_queue.exit(program_id)?;
+    }
+
+    let is_native = token_mint.key() == spl_token::native_mint::id();
+    let free_fee_bet = accounts.player_stats.free_fee_bets > 0;
+    let (fee, loyalty_points) = accounts.player_stats.charge_fee(amount, core_state)?;
 
-    let block_hash = recent_blockhashes::id();
-    let slot_hash = slot_hashes::id();
-    [block_hash, slot_hash].hash(&mut hasher);
-    let hash0 = hasher.finish();
-    let mut hasher = DefaultHasher::new();
-    
-    [hash0, clock, flip_counter].hash(&mut hasher);
-    let hash = hasher.finish();
+    if !is_native {
+        utils::assert_is_ata(user_token_account, &user.key(), &token_mint.key())?;
+    } else {
+        utils::assert_keys_equal(user_token_account.key(), user.key())?;
+        utils::assert_keys_equal(vault_token_account.key(), vault_authority.key())?;
+    }
+    // the amount backs the bankroll, the fee goes to the fee vault
+    utils::transfer_to_vault(
+        user_token_account,
+        vault_token_account,
+        &user.to_account_info(),
+        token_program,
+        system_program,
+        amount,
+        is_native,
+    )?;
+    if fee > 0 {
+        utils::transfer_to_vault(
+            user_token_account,
+            &accounts.fee_vault,
+            &user.to_account_info(),
+            token_program,
+            system_program,
+            fee,
+            is_native,
+        )?;
+    }
+
+    let vault_balance = utils::vault_balance(vault_token_account, is_native)?;
+    utils::assert_solvent(vault_balance, accounts.mint_ledger.pending_liability, payout)?;
+
+    let clock = Clock::get()?;
+    let allowed_bets = &accounts.allowed_bets;
+    utils::assert_max_payout(vault_balance, accounts.mint_ledger.pending_liability, payout, allowed_bets.max_payout_bps)?;
+    accounts.mint_ledger.record_volume(clock.slot, amount, allowed_bets)?;
+    accounts.user_exposure.record_bet(payout, allowed_bets.max_user_liability)?;
+    accounts.user_exposure.user = user.key();
+    accounts.user_exposure.token_mint = token_mint.key();
+    accounts.player_stats.user = user.key();
+
+    accounts.mint_ledger.record_bet(amount, fee, payout)?;
+    // credited in settle_bet
+    let (referrer, referral_reward) = calc_referral_reward(
+        core_state,
+        &user.key(),
+        &token_mint.key(),
+        referrer,
+        remaining_accounts,
+        fee,
+    )?;
+
+    accounts.bet_state.core_state = core_state.key();
+    accounts.bet_state.bet_state_nonce = bet_state_nonce;
+    accounts.bet_state.user = user.key();
+    accounts.bet_state.token_mint = token_mint.key();
+    accounts.bet_state.amount = amount;
+    accounts.bet_state.fee = fee;
+    accounts.bet_state.payout = payout;
+    accounts.bet_state.referrer = referrer;
+    accounts.bet_state.referral_reward = referral_reward;
+    accounts.bet_state.loyalty_points = loyalty_points;
+    accounts.bet_state.free_fee_bet = free_fee_bet;
+    accounts.bet_state.flip_counter = flip_counter;
+    accounts.bet_state.client_seed = client_seed;
+    accounts.bet_state.commitment = commitment;
+    accounts.bet_state.placed_slot = clock.slot;
+    accounts.bet_state.placed_at = clock.unix_timestamp;
+    accounts.bet_state.expires_at = clock.unix_timestamp
+        .checked_add(core_state.settle_timeout)
+        .ok_or(ErrorCode::NumericalOverflow)?;
+    accounts.bet_state.randomness_mode = core_state.randomness_mode;
+    accounts.bet_state.game = game;
+    accounts.bet_state.win_ratio = core_state.win_ratio;
+    accounts.bet_state.fee_percent = core_state.fee_percent;
+    accounts.bet_state.payout_multiplier_bps = payout_multiplier_bps;
+    if core_state.randomness_mode == RandomnessMode::Ed25519Oracle {
+        accounts.bet_state.oracle = core_state.oracle;
+    }
+    accounts.bet_state.approved = true;
+
+    // with admin-funded rent the sponsor pays the user back for bet_state, and gets
+    // the rent back when it is closed
+    if core_state.admin_funded_rent {
+        let rent_sponsor = &accounts.rent_sponsor;
+        let rent_sponsor_seeds = [
+            RENT_SPONSOR_SEED.as_bytes(),
+            core_state.house_id.as_ref(),
+            &[rent_sponsor_bump],
+        ];
+        anchor_lang::solana_program::program::invoke_signed(
+            &anchor_lang::solana_program::system_instruction::transfer(
+                &rent_sponsor.key(),
+                &user.key(),
+                accounts.bet_state.to_account_info().lamports(),
+            ),
+            &[
+                rent_sponsor.to_account_info(),
+                user.to_account_info(),
+                system_program.to_account_info(),
+            ],
+            &[&rent_sponsor_seeds],
+        )?;
+        accounts.bet_state.rent_payer = rent_sponsor.key();
+    } else {
+        accounts.bet_state.rent_payer = user.key();
+    }
+
+    let bet_state = &accounts.bet_state;
+    emit!(BetPlaced {
+        bet_state: bet_state.key(),
+        user: bet_state.user,
+        token_mint: bet_state.token_mint,
+        flip_counter: bet_state.flip_counter,
+        game: bet_state.game,
+        randomness_mode: bet_state.randomness_mode,
+        client_seed: bet_state.client_seed,
+        commitment: bet_state.commitment,
+        amount: bet_state.amount,
+        fee: bet_state.fee,
+        payout: bet_state.payout,
+    });
+
+    Ok(())
+}
+
+/// The referrer's share of `fee`, and the referrer it is owed to. With a referrer set,
+/// remaining_accounts hold the user's ReferralLink and the referrer's Referrer account
+/// for the mint. Pending bets are only credited when they settle, so a refunded bet
+/// never pays its referrer.
+pub fn calc_referral_reward(
+    core_state: &Account<CoreState>,
+    user: &Pubkey,
+    token_mint: &Pubkey,
+    referrer: Option<Pubkey>,
+    remaining_accounts: &[AccountInfo],
+    fee: u64,
+) -> Result<(Pubkey, u64)> {
+    let referrer = match referrer {
+        Some(referrer) if core_state.referral_share_bps > 0 => referrer,
+        _ => return Ok((Pubkey::default(), 0)),
+    };
+    if remaining_accounts.len() < 2 {
+        return Err(ErrorCode::MissingReferralAccounts.into());
+    }
+
+    let referral_link: Account<ReferralLink> = Account::try_from(&remaining_accounts[0])?;
+    if referral_link.user != *user || referral_link.referrer != referrer {
+        return Err(ErrorCode::ReferrerMismatch.into());
+    }
+    if referral_link.core_state != core_state.key() {
+        return Err(ErrorCode::InvalidCoreState.into());
+    }
+    load_referrer(&remaining_accounts[1], &core_state.key(), &referrer, token_mint)?;
+
+    let reward = (fee as u128 * core_state.referral_share_bps as u128 / 10000) as u64;
+    Ok((referrer, reward))
+}
+
+/// Adds `reward` to the referrer's accrued balance.
+pub fn credit_referral_reward(
+    referrer_account: &AccountInfo,
+    core_state: &Pubkey,
+    referrer: &Pubkey,
+    token_mint: &Pubkey,
+    reward: u64,
+    program_id: &Pubkey,
+) -> Result<()> {
+    let mut referrer_account = load_referrer(referrer_account, core_state, referrer, token_mint)?;
+    referrer_account.accrued = referrer_account.accrued.checked_add(reward).ok_or(ErrorCode::NumericalOverflow)?;
+    referrer_account.total_earned = referrer_account.total_earned.checked_add(reward).ok_or(ErrorCode::NumericalOverflow)?;
+    referrer_account.exit(program_id)
+}
+
+fn load_referrer<'a>(
+    referrer_account: &AccountInfo<'a>,
+    core_state: &Pubkey,
+    referrer: &Pubkey,
+    token_mint: &Pubkey,
+) -> Result<Account<'a, Referrer>> {
+    let referrer_account: Account<Referrer> = Account::try_from(referrer_account)?;
+    if referrer_account.referrer != *referrer || referrer_account.token_mint != *token_mint {
+        return Err(ErrorCode::InvalidReferrer.into());
+    }
+    if referrer_account.core_state != *core_state {
+        return Err(ErrorCode::InvalidCoreState.into());
+    }
+    Ok(referrer_account)
+}
+
+pub fn emit_core_state_updated(core_state: &Account<CoreState>, user: Pubkey) {
+    emit!(CoreStateUpdated {
+        core_state: core_state.key(),
+        user,
+        flip_counter: core_state.flip_counter,
+        active: core_state.active,
+        allow_direct_bet: core_state.allow_direct_bet,
+        fee_percent: core_state.fee_percent,
+        win_ratio: core_state.win_ratio,
+        payout_multiplier_bps: core_state.payout_multiplier_bps,
+        randomness_mode: core_state.randomness_mode,
+    });
+}
+
+/// Pays out a pending bet. Odds and payout come from the BetState snapshot only, so
+/// CoreState changes after placement do not affect the bet. With a referral reward on
+/// the bet, remaining_accounts hold the referrer's Referrer account for the mint.
+pub fn settle_bet(
+    accounts: &mut BetReturn,
+    remaining_accounts: &[AccountInfo],
+    program_id: &Pubkey,
+    outcome: randomness::Outcome,
+    is_win: bool,
+) -> Result<()> {
+    accounts.bet_state.approved = false;
+
+    let core_state = &accounts.core_state;
+    let bet_state = &accounts.bet_state;
+    let user = &accounts.user;
+    let vault_authority = &accounts.vault_authority;
+    let token_mint = &accounts.token_mint;
+    let user_token_account = &accounts.user_token_account;
+    let vault_token_account = &accounts.vault_token_account;
+    let token_program = &accounts.token_program;
+    let system_program = &accounts.system_program;
+
+    let is_native = token_mint.key() == spl_token::native_mint::id();
+    let paid_out = if is_win { bet_state.payout } else { 0 };
+    accounts.mint_ledger.record_settlement(bet_state.fee, bet_state.payout, paid_out)?;
+    if bet_state.referral_reward > 0 {
+        let referrer_account = remaining_accounts.first().ok_or(ErrorCode::MissingReferralAccounts)?;
+        credit_referral_reward(
+            referrer_account,
+            &core_state.key(),
+            &bet_state.referrer,
+            &bet_state.token_mint,
+            bet_state.referral_reward,
+            program_id,
+        )?;
+        accounts.mint_ledger.record_referral_reward(bet_state.referral_reward)?;
+    }
+    accounts.user_exposure.release(bet_state.payout)?;
+    accounts.player_stats.record(&bet_state.token_mint, bet_state.amount, paid_out, bet_state.placed_slot)?;
+    accounts.player_stats.credit_loyalty_points(bet_state.loyalty_points)?;
+
+    if is_win {
+        let vault_auth_seeds = [
+            VAULT_AUTH_SEED.as_bytes(),
+            core_state.house_id.as_ref(),
+            &[core_state.vault_auth_nonce],
+        ];
+
+        if !is_native {
+            utils::assert_is_ata(user_token_account, &user.key(), &token_mint.key())?;
+            anchor_lang::solana_program::program::invoke_signed(
+                &spl_token::instruction::transfer(
+                    &token_program.key(),
+                    &vault_token_account.key(),
+                    &user_token_account.key(),
+                    &vault_authority.key(),
+                    &[],
+                    bet_state.payout,
+                )?,
+                &[
+                    vault_token_account.to_account_info(),
+                    user_token_account.to_account_info(),
+                    token_program.to_account_info(),
+                    vault_authority.to_account_info(),
+                ],
+                &[&vault_auth_seeds],
+            )?;
+        } else {
+            utils::assert_keys_equal(user_token_account.key(), user.key())?;
+            utils::assert_keys_equal(vault_token_account.key(), vault_authority.key())?;
+            anchor_lang::solana_program::program::invoke_signed(
+                &anchor_lang::solana_program::system_instruction::transfer(
+                    &vault_token_account.key(),
+                    &user_token_account.key(),
+                    bet_state.payout,
+                ),
+                &[
+                    vault_token_account.to_account_info(),
+                    user_token_account.to_account_info(),
+                    system_program.to_account_info(),
+                    user.to_account_info(),
+                ],
+                &[&vault_auth_seeds],
+            )?;
+        }
+        msg!("Congratulations, You won!");
+    }
+    else {
+        msg!("Sorry, You lost!");
+    }
 
-    return hash;
+    emit!(BetSettled {
+        bet_state: bet_state.key(),
+        user: bet_state.user,
+        token_mint: bet_state.token_mint,
+        flip_counter: bet_state.flip_counter,
+        game: bet_state.game,
+        randomness_mode: bet_state.randomness_mode,
+        client_seed: bet_state.client_seed,
+        entropy: outcome.entropy,
+        roll: outcome.roll,
+        secret: outcome.secret,
+        slot_hash: outcome.slot_hash,
+        signature: outcome.signature,
+        is_win,
+        amount: bet_state.amount,
+        fee: bet_state.fee,
+        paid_out,
+    });
+
+    Ok(())
 }
 
 // -------------------------------------------------------------------------------- //
@@ -464,15 +1688,15 @@ pub struct Initialize<'info> {
     pub admin: Signer<'info>,
     #[account(
         init,
-        space = 8 + 1 + 1 + 8 + 2 + 2 + 1 + 1 + std::mem::size_of::<Pubkey>() + std::mem::size_of::<Pubkey>(),
-        seeds = [CORE_STATE_SEED.as_bytes(), admin.key().as_ref()],
+        space = 8 + 1 + 1 + 8 + 2 + 2 + 1 + 1 + std::mem::size_of::<Pubkey>() + 4 + MAX_EXECUTERS * std::mem::size_of::<Pubkey>() + std::mem::size_of::<Pubkey>() + 1 + 8 + 1 + 4 + 2 + 2 + 2 * std::mem::size_of::<Pubkey>() + 3 * std::mem::size_of::<Pubkey>() + 4 + MAX_WITHDRAW_DESTINATIONS * std::mem::size_of::<Pubkey>() + 8 + 2 + std::mem::size_of::<Pubkey>() + 2 + 8,
+        seeds = [CORE_STATE_SEED.as_bytes(), args.house_id.as_ref()],
         bump,
         payer = admin,
     )]
     pub core_state: Account<'info, CoreState>,
     /// CHECK: 
     #[account(
-        seeds = [VAULT_AUTH_SEED.as_bytes(), admin.key().as_ref()],
+        seeds = [VAULT_AUTH_SEED.as_bytes(), args.house_id.as_ref()],
         bump = args.vault_auth_nonce,
     )]
     pub vault_authority: AccountInfo<'info>,
@@ -488,94 +1712,715 @@ pub struct UpdateCoreState<'info> {
     pub admin: Signer<'info>,
     #[account(
         mut,
-        seeds = [CORE_STATE_SEED.as_bytes(), admin.key().as_ref()],
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
         bump = core_state.core_state_nonce,
     )]
     pub core_state: Account<'info, CoreState>,
 }
 
 #[derive(Accounts)]
-#[instruction(args: RegisterArgs)]
-pub struct Register<'info> {
-    #[account(
-        seeds = [CORE_STATE_SEED.as_bytes(), admin.key().as_ref()],
-        bump = core_state.core_state_nonce,
-        constraint = core_state.active @ ErrorCode::NotActiveCoreState,
-    )]
-    pub core_state: Account<'info, CoreState>,
+pub struct ProposeConfig<'info> {
     #[account(
         mut,
-        constraint = admin.key() == core_state.admin @ ErrorCode::WrongAdmin,
+        constraint = core_state.admin == admin.key() @ ErrorCode::WrongAdmin,
     )]
     pub admin: Signer<'info>,
-    pub token_mint: Account<'info, Mint>,
-    /// CHECK:
     #[account(
-        mut,
-        seeds = [VAULT_AUTH_SEED.as_bytes(), admin.key().as_ref()],
-        bump = core_state.vault_auth_nonce,
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.core_state_nonce,
     )]
-    pub vault_authority: AccountInfo<'info>,
+    pub core_state: Account<'info, CoreState>,
     #[account(
-        init,
-        token::mint = token_mint,
-        token::authority = vault_authority,
-        seeds = [VAULT_TOKEN_ACCOUNT_SEED.as_bytes(), token_mint.key().as_ref(), admin.key().as_ref()],
+        init_if_needed,
+        space = 8 + std::mem::size_of::<Pubkey>() + 2 + 2 + 8 + 8,
+        seeds = [PENDING_CONFIG_SEED.as_bytes(), core_state.house_id.as_ref()],
         bump,
         payer = admin,
     )]
-    pub vault_token_account: Account<'info, TokenAccount>,
+    pub pending_config: Account<'info, PendingConfig>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ApplyConfig<'info> {
     #[account(
-        init,
+        mut,
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.core_state_nonce,
+    )]
+    pub core_state: Account<'info, CoreState>,
+    #[account(
+        mut,
+        seeds = [PENDING_CONFIG_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump,
+        close = admin,
+    )]
+    pub pending_config: Account<'info, PendingConfig>,
+    /// CHECK: receives the PendingConfig rent
+    #[account(
+        mut,
+        constraint = admin.key() == core_state.admin @ ErrorCode::WrongAdmin,
+    )]
+    pub admin: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetActive<'info> {
+    #[account(
+        constraint = pauser.key() == core_state.pauser @ ErrorCode::WrongPauser,
+    )]
+    pub pauser: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.core_state_nonce,
+    )]
+    pub core_state: Account<'info, CoreState>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAdmin<'info> {
+    #[account(
+        constraint = core_state.pending_admin == new_admin.key() @ ErrorCode::NotPendingAdmin,
+    )]
+    pub new_admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.core_state_nonce,
+    )]
+    pub core_state: Account<'info, CoreState>,
+}
+
+#[derive(Accounts)]
+#[instruction(args: RegisterArgs)]
+pub struct Register<'info> {
+    #[account(
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.core_state_nonce,
+        constraint = core_state.active @ ErrorCode::NotActiveCoreState,
+    )]
+    pub core_state: Account<'info, CoreState>,
+    #[account(
+        mut,
+        constraint = admin.key() == core_state.admin @ ErrorCode::WrongAdmin,
+    )]
+    pub admin: Signer<'info>,
+    pub token_mint: Account<'info, Mint>,
+    /// CHECK:
+    #[account(
+        mut,
+        seeds = [VAULT_AUTH_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.vault_auth_nonce,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        init,
+        token::mint = token_mint,
+        token::authority = vault_authority,
+        seeds = [VAULT_TOKEN_ACCOUNT_SEED.as_bytes(), token_mint.key().as_ref(), core_state.house_id.as_ref()],
+        bump,
+        payer = admin,
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+    #[account(
+        init,
+        token::mint = token_mint,
+        token::authority = vault_authority,
+        seeds = [FEE_VAULT_SEED.as_bytes(), token_mint.key().as_ref(), core_state.house_id.as_ref()],
+        bump,
+        payer = admin,
+    )]
+    pub fee_vault_token_account: Box<Account<'info, TokenAccount>>,
+    /// CHECK: holds native fees as lamports
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump,
+    )]
+    pub native_fee_vault: AccountInfo<'info>,
+    #[account(
+        init,
+        payer = admin,
+        space = AllowedBets::space(args.amounts.len()),
+        seeds = [ALLOWED.as_bytes(), token_mint.key().as_ref(), core_state.house_id.as_ref()],
+        bump
+    )]
+    pub allowed_bets: Account<'info, AllowedBets>,
+    #[account(
+        init,
         payer = admin,
-        space = 100,
-        seeds = [ALLOWED.as_bytes(), token_mint.key().as_ref(), core_state.admin.key().as_ref()],
+        space = 8 + std::mem::size_of::<Pubkey>() + std::mem::size_of::<Pubkey>() + 8 + 8 + 8 + 8 + 8 + std::mem::size_of::<Pubkey>() + 8 + 8 + std::mem::size_of::<Pubkey>() + 1 + 8 + 8 + 8 + std::mem::size_of::<Pubkey>(),
+        seeds = [MINT_LEDGER_SEED.as_bytes(), token_mint.key().as_ref(), core_state.house_id.as_ref()],
+        bump
+    )]
+    pub mint_ledger: Box<Account<'info, MintLedger>>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateAllowedBets<'info> {
+    #[account(
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.core_state_nonce,
+    )]
+    pub core_state: Account<'info, CoreState>,
+    #[account(
+        mut,
+        constraint = risk_manager.key() == core_state.risk_manager @ ErrorCode::WrongRiskManager,
+    )]
+    pub risk_manager: Signer<'info>,
+    pub token_mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [ALLOWED.as_bytes(), token_mint.key().as_ref(), core_state.house_id.as_ref()],
+        bump
+    )]
+    pub allowed_bets: Account<'info, AllowedBets>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DeregisterMint<'info> {
+    #[account(
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.core_state_nonce,
+    )]
+    pub core_state: Box<Account<'info, CoreState>>,
+    #[account(
+        mut,
+        constraint = admin.key() == core_state.admin @ ErrorCode::WrongAdmin,
+    )]
+    pub admin: Signer<'info>,
+    pub token_mint: Account<'info, Mint>,
+    /// CHECK: checked against CoreState.withdraw_destinations
+    pub destination: AccountInfo<'info>,
+    /// CHECK:
+    #[account(mut)]
+    pub destination_token_account: UncheckedAccount<'info>,
+    /// CHECK:
+    #[account(
+        mut,
+        seeds = [VAULT_AUTH_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.vault_auth_nonce,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        mut,
+        seeds = [VAULT_TOKEN_ACCOUNT_SEED.as_bytes(), token_mint.key().as_ref(), core_state.house_id.as_ref()],
+        bump,
+    )]
+    pub vault_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_SEED.as_bytes(), token_mint.key().as_ref(), core_state.house_id.as_ref()],
+        bump,
+    )]
+    pub fee_vault_token_account: Box<Account<'info, TokenAccount>>,
+    /// CHECK:
+    #[account(
+        mut,
+        seeds = [FEE_VAULT_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = mint_ledger.fee_vault_nonce,
+    )]
+    pub native_fee_vault: AccountInfo<'info>,
+    #[account(
+        mut,
+        close = admin,
+        seeds = [ALLOWED.as_bytes(), token_mint.key().as_ref(), core_state.house_id.as_ref()],
+        bump
+    )]
+    pub allowed_bets: Account<'info, AllowedBets>,
+    #[account(
+        mut,
+        close = admin,
+        seeds = [MINT_LEDGER_SEED.as_bytes(), token_mint.key().as_ref(), core_state.house_id.as_ref()],
+        bump,
+        constraint = mint_ledger.pending_bets == 0 @ ErrorCode::PendingBetsExist,
+        constraint = mint_ledger.referral_owed == 0 @ ErrorCode::ReferralRewardsOwed,
+        constraint = mint_ledger.lp_mint == Pubkey::default() @ ErrorCode::LiquidityPoolOpen,
+    )]
+    pub mint_ledger: Box<Account<'info, MintLedger>>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimFees<'info> {
+    #[account(
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.core_state_nonce,
+    )]
+    pub core_state: Box<Account<'info, CoreState>>,
+    #[account(
+        constraint = treasurer.key() == core_state.treasurer @ ErrorCode::WrongTreasurer,
+    )]
+    pub treasurer: Signer<'info>,
+    /// CHECK:
+    #[account(
+        seeds = [VAULT_AUTH_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.vault_auth_nonce,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    pub token_mint: Account<'info, Mint>,
+    /// CHECK: checked against CoreState.withdraw_destinations
+    pub destination: AccountInfo<'info>,
+    /// CHECK:
+    #[account(mut)]
+    pub destination_token_account: UncheckedAccount<'info>,
+    /// CHECK:
+    #[account(
+        mut,
+        constraint = fee_vault.key() == mint_ledger.fee_vault @ ErrorCode::InvalidFeeVault,
+    )]
+    pub fee_vault: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [MINT_LEDGER_SEED.as_bytes(), token_mint.key().as_ref(), core_state.house_id.as_ref()],
+        bump
+    )]
+    pub mint_ledger: Box<Account<'info, MintLedger>>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterReferral<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.core_state_nonce,
+    )]
+    pub core_state: Account<'info, CoreState>,
+    #[account(
+        init,
+        space = 8 + 3 * std::mem::size_of::<Pubkey>(),
+        seeds = [REFERRAL_LINK_SEED.as_bytes(), user.key().as_ref(), core_state.house_id.as_ref()],
+        bump,
+        payer = user,
+    )]
+    pub referral_link: Account<'info, ReferralLink>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitReferrer<'info> {
+    #[account(mut)]
+    pub referrer: Signer<'info>,
+    #[account(
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.core_state_nonce,
+    )]
+    pub core_state: Account<'info, CoreState>,
+    pub token_mint: Account<'info, Mint>,
+    #[account(
+        init,
+        space = 8 + 3 * std::mem::size_of::<Pubkey>() + 8 + 8,
+        seeds = [REFERRER_SEED.as_bytes(), token_mint.key().as_ref(), referrer.key().as_ref(), core_state.house_id.as_ref()],
+        bump,
+        payer = referrer,
+    )]
+    pub referrer_account: Account<'info, Referrer>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimReferralRewards<'info> {
+    pub referrer: Signer<'info>,
+    #[account(
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.core_state_nonce,
+    )]
+    pub core_state: Box<Account<'info, CoreState>>,
+    /// CHECK:
+    #[account(
+        seeds = [VAULT_AUTH_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.vault_auth_nonce,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    pub token_mint: Account<'info, Mint>,
+    /// CHECK:
+    #[account(mut)]
+    pub referrer_token_account: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [REFERRER_SEED.as_bytes(), token_mint.key().as_ref(), referrer.key().as_ref(), core_state.house_id.as_ref()],
+        bump,
+    )]
+    pub referrer_account: Account<'info, Referrer>,
+    /// CHECK:
+    #[account(
+        mut,
+        constraint = fee_vault.key() == mint_ledger.fee_vault @ ErrorCode::InvalidFeeVault,
+    )]
+    pub fee_vault: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [MINT_LEDGER_SEED.as_bytes(), token_mint.key().as_ref(), core_state.house_id.as_ref()],
+        bump
+    )]
+    pub mint_ledger: Box<Account<'info, MintLedger>>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetLoyaltyMint<'info> {
+    #[account(
+        mut,
+        constraint = core_state.admin == admin.key() @ ErrorCode::WrongAdmin,
+    )]
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.core_state_nonce,
+    )]
+    pub core_state: Account<'info, CoreState>,
+    /// CHECK:
+    #[account(
+        seeds = [VAULT_AUTH_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.vault_auth_nonce,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        init_if_needed,
+        mint::decimals = 0,
+        mint::authority = vault_authority,
+        seeds = [LOYALTY_MINT_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump,
+        payer = admin,
+    )]
+    pub loyalty_mint: Box<Account<'info, Mint>>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimLoyaltyPoints<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.core_state_nonce,
+    )]
+    pub core_state: Box<Account<'info, CoreState>>,
+    /// CHECK:
+    #[account(
+        seeds = [VAULT_AUTH_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.vault_auth_nonce,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        mut,
+        constraint = loyalty_mint.key() == core_state.loyalty_mint @ ErrorCode::InvalidLoyaltyMint,
+    )]
+    pub loyalty_mint: Box<Account<'info, Mint>>,
+    #[account(
+        init_if_needed,
+        associated_token::mint = loyalty_mint,
+        associated_token::authority = user,
+        payer = user,
+    )]
+    pub user_loyalty_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        seeds = [PLAYER_STATS_SEED.as_bytes(), user.key().as_ref(), core_state.house_id.as_ref()],
+        bump,
+    )]
+    pub player_stats: Box<Account<'info, PlayerStats>>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct RedeemLoyaltyPoints<'info> {
+    pub user: Signer<'info>,
+    #[account(
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.core_state_nonce,
+    )]
+    pub core_state: Box<Account<'info, CoreState>>,
+    #[account(
+        mut,
+        constraint = loyalty_mint.key() == core_state.loyalty_mint @ ErrorCode::InvalidLoyaltyMint,
+    )]
+    pub loyalty_mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        constraint = user_loyalty_account.mint == loyalty_mint.key() @ ErrorCode::TokenMintMismatch,
+        constraint = user_loyalty_account.owner == user.key() @ ErrorCode::TokenOnwerMismatch,
+    )]
+    pub user_loyalty_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [PLAYER_STATS_SEED.as_bytes(), user.key().as_ref(), core_state.house_id.as_ref()],
+        bump,
+    )]
+    pub player_stats: Box<Account<'info, PlayerStats>>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(args: SetRiskLimitsArgs)]
+pub struct SetRiskLimits<'info> {
+    #[account(
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.core_state_nonce,
+    )]
+    pub core_state: Account<'info, CoreState>,
+    #[account(
+        constraint = risk_manager.key() == core_state.risk_manager @ ErrorCode::WrongRiskManager,
+    )]
+    pub risk_manager: Signer<'info>,
+    pub token_mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        seeds = [ALLOWED.as_bytes(), token_mint.key().as_ref(), core_state.house_id.as_ref()],
+        bump
+    )]
+    pub allowed_bets: Account<'info, AllowedBets>,
+}
+
+#[derive(Accounts)]
+pub struct InitCommitmentQueue<'info> {
+    #[account(
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.core_state_nonce,
+    )]
+    pub core_state: Account<'info, CoreState>,
+    #[account(
+        mut,
+        constraint = admin.key() == core_state.admin @ ErrorCode::WrongAdmin,
+    )]
+    pub admin: Signer<'info>,
+    #[account(
+        init,
+        space = 8 + std::mem::size_of::<Pubkey>() + 4 + MAX_COMMITMENTS * 32,
+        seeds = [COMMITMENT_QUEUE_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump,
+        payer = admin,
+    )]
+    pub commitment_queue: Account<'info, CommitmentQueue>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(args: PushCommitmentsArgs)]
+pub struct PushCommitments<'info> {
+    #[account(
+        constraint = core_state.is_executer(&executer.key()) @ ErrorCode::WrongExecuter,
+    )]
+    pub executer: Signer<'info>,
+    #[account(
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.core_state_nonce,
+    )]
+    pub core_state: Account<'info, CoreState>,
+    #[account(
+        mut,
+        seeds = [COMMITMENT_QUEUE_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump,
+        constraint = commitment_queue.core_state == core_state.key() @ ErrorCode::InvalidCoreState,
+    )]
+    pub commitment_queue: Account<'info, CommitmentQueue>,
+}
+
+#[derive(Accounts)]
+#[instruction(args: WithdrawArgs)]
+pub struct WithdrawRentSponsor<'info> {
+    #[account(
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.core_state_nonce,
+    )]
+    pub core_state: Account<'info, CoreState>,
+    #[account(
+        mut,
+        constraint = admin.key() == core_state.admin @ ErrorCode::WrongAdmin,
+    )]
+    pub admin: Signer<'info>,
+    /// CHECK:
+    #[account(
+        mut,
+        seeds = [RENT_SPONSOR_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump,
+    )]
+    pub rent_sponsor: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(args: DepositArgs)]
+pub struct Deposit<'info> {
+    #[account(
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.core_state_nonce,
+        constraint = core_state.active @ ErrorCode::NotActiveCoreState,
+    )]
+    pub core_state: Account<'info, CoreState>,
+    #[account(
+        mut,
+        constraint = treasurer.key() == core_state.treasurer @ ErrorCode::WrongTreasurer,
+    )]
+    pub treasurer: Signer<'info>,
+    /// CHECK:
+    #[account(
+        mut,
+        seeds = [VAULT_AUTH_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.vault_auth_nonce,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    pub token_mint: Account<'info, Mint>,
+    /// CHECK:
+    #[account(mut)]
+    pub treasurer_token_account: UncheckedAccount<'info>,
+    /// CHECK:
+    #[account(mut)]
+    pub vault_token_account: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(args: WithdrawArgs)]
+pub struct Withdraw<'info> {
+    #[account(
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.core_state_nonce,
+        constraint = core_state.active @ ErrorCode::NotActiveCoreState,
+    )]
+    pub core_state: Account<'info, CoreState>,
+    #[account(
+        constraint = treasurer.key() == core_state.treasurer @ ErrorCode::WrongTreasurer,
+    )]
+    pub treasurer: Signer<'info>,
+    /// CHECK:
+    #[account(
+        mut,
+        seeds = [VAULT_AUTH_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.vault_auth_nonce,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    pub token_mint: Account<'info, Mint>,
+    /// CHECK: checked against CoreState.withdraw_destinations
+    pub destination: AccountInfo<'info>,
+    /// CHECK:
+    #[account(mut)]
+    pub destination_token_account: UncheckedAccount<'info>,
+    /// CHECK:
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == mint_ledger.vault @ ErrorCode::InvalidVault,
+    )]
+    pub vault_token_account: UncheckedAccount<'info>,
+    #[account(
+        seeds = [MINT_LEDGER_SEED.as_bytes(), token_mint.key().as_ref(), core_state.house_id.as_ref()],
         bump
     )]
-    pub allowed_bets: Account<'info, AllowedBets>,
-    pub system_program: Program<'info, System>,
+    pub mint_ledger: Box<Account<'info, MintLedger>>,
+
     pub token_program: Program<'info, Token>,
-    pub rent: Sysvar<'info, Rent>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-#[instruction(args: DepositArgs)]
-pub struct Deposit<'info> {
+pub struct MigrateVault<'info> {
     #[account(
-        seeds = [CORE_STATE_SEED.as_bytes(), admin.key().as_ref()],
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
         bump = core_state.core_state_nonce,
-        constraint = core_state.active @ ErrorCode::NotActiveCoreState,
     )]
-    pub core_state: Account<'info, CoreState>,
+    pub core_state: Box<Account<'info, CoreState>>,
     #[account(
         mut,
         constraint = admin.key() == core_state.admin @ ErrorCode::WrongAdmin,
     )]
     pub admin: Signer<'info>,
+    pub token_mint: Account<'info, Mint>,
+    /// CHECK: layout is checked in utils::assert_legacy_core_state
+    #[account(
+        seeds = [CORE_STATE_SEED.as_bytes(), admin.key().as_ref()],
+        bump,
+    )]
+    pub legacy_core_state: UncheckedAccount<'info>,
     /// CHECK:
     #[account(
         mut,
         seeds = [VAULT_AUTH_SEED.as_bytes(), admin.key().as_ref()],
+        bump,
+    )]
+    pub legacy_vault_authority: AccountInfo<'info>,
+    /// CHECK:
+    #[account(
+        mut,
+        seeds = [VAULT_TOKEN_ACCOUNT_SEED.as_bytes(), token_mint.key().as_ref(), admin.key().as_ref()],
+        bump,
+    )]
+    pub legacy_vault_token_account: UncheckedAccount<'info>,
+    /// CHECK:
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == mint_ledger.vault @ ErrorCode::InvalidVault,
+    )]
+    pub vault_token_account: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [MINT_LEDGER_SEED.as_bytes(), token_mint.key().as_ref(), core_state.house_id.as_ref()],
+        bump
+    )]
+    pub mint_ledger: Box<Account<'info, MintLedger>>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RefundLegacyBet<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.core_state_nonce,
+    )]
+    pub core_state: Box<Account<'info, CoreState>>,
+    /// CHECK:
+    #[account(
+        mut,
+        seeds = [VAULT_AUTH_SEED.as_bytes(), core_state.house_id.as_ref()],
         bump = core_state.vault_auth_nonce,
     )]
     pub vault_authority: AccountInfo<'info>,
     pub token_mint: Account<'info, Mint>,
     /// CHECK:
     #[account(mut)]
-    pub admin_token_account: UncheckedAccount<'info>,
+    pub user_token_account: UncheckedAccount<'info>,
     /// CHECK:
-    #[account(mut)]
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == mint_ledger.vault @ ErrorCode::InvalidVault,
+    )]
     pub vault_token_account: UncheckedAccount<'info>,
-
+    /// CHECK: layout is checked in utils::load_legacy_bet_state
+    #[account(mut)]
+    pub legacy_bet_state: UncheckedAccount<'info>,
+    #[account(
+        seeds = [MINT_LEDGER_SEED.as_bytes(), token_mint.key().as_ref(), core_state.house_id.as_ref()],
+        bump
+    )]
+    pub mint_ledger: Box<Account<'info, MintLedger>>,
     pub token_program: Program<'info, Token>,
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-#[instruction(args: WithdrawArgs)]
-pub struct Withdraw<'info> {
+pub struct InitLpPool<'info> {
     #[account(
-        seeds = [CORE_STATE_SEED.as_bytes(), admin.key().as_ref()],
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
         bump = core_state.core_state_nonce,
         constraint = core_state.active @ ErrorCode::NotActiveCoreState,
     )]
@@ -585,21 +2430,135 @@ pub struct Withdraw<'info> {
         constraint = admin.key() == core_state.admin @ ErrorCode::WrongAdmin,
     )]
     pub admin: Signer<'info>,
+    pub token_mint: Account<'info, Mint>,
+    /// CHECK:
+    #[account(
+        seeds = [VAULT_AUTH_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.vault_auth_nonce,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    #[account(
+        init,
+        mint::decimals = token_mint.decimals,
+        mint::authority = vault_authority,
+        seeds = [LP_MINT_SEED.as_bytes(), token_mint.key().as_ref(), core_state.house_id.as_ref()],
+        bump,
+        payer = admin,
+    )]
+    pub lp_mint: Box<Account<'info, Mint>>,
+    #[account(
+        init,
+        associated_token::mint = lp_mint,
+        associated_token::authority = admin,
+        payer = admin,
+    )]
+    pub admin_lp_account: Box<Account<'info, TokenAccount>>,
     /// CHECK:
+    #[account(
+        constraint = vault_token_account.key() == mint_ledger.vault @ ErrorCode::InvalidVault,
+    )]
+    pub vault_token_account: UncheckedAccount<'info>,
     #[account(
         mut,
-        seeds = [VAULT_AUTH_SEED.as_bytes(), admin.key().as_ref()],
+        seeds = [MINT_LEDGER_SEED.as_bytes(), token_mint.key().as_ref(), core_state.house_id.as_ref()],
+        bump
+    )]
+    pub mint_ledger: Box<Account<'info, MintLedger>>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct AddLiquidity<'info> {
+    #[account(
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.core_state_nonce,
+        constraint = core_state.active @ ErrorCode::NotActiveCoreState,
+    )]
+    pub core_state: Box<Account<'info, CoreState>>,
+    #[account(mut)]
+    pub provider: Signer<'info>,
+    /// CHECK:
+    #[account(
+        mut,
+        seeds = [VAULT_AUTH_SEED.as_bytes(), core_state.house_id.as_ref()],
         bump = core_state.vault_auth_nonce,
     )]
     pub vault_authority: AccountInfo<'info>,
     pub token_mint: Account<'info, Mint>,
     /// CHECK:
     #[account(mut)]
-    pub admin_token_account: UncheckedAccount<'info>,
+    pub provider_token_account: UncheckedAccount<'info>,
     /// CHECK:
-    #[account(mut)]
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == mint_ledger.vault @ ErrorCode::InvalidVault,
+    )]
     pub vault_token_account: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        constraint = lp_mint.key() == mint_ledger.lp_mint @ ErrorCode::InvalidTokenMint,
+    )]
+    pub lp_mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        constraint = provider_lp_account.mint == lp_mint.key() @ ErrorCode::TokenMintMismatch,
+        constraint = provider_lp_account.owner == provider.key() @ ErrorCode::TokenOnwerMismatch,
+    )]
+    pub provider_lp_account: Account<'info, TokenAccount>,
+    #[account(
+        seeds = [MINT_LEDGER_SEED.as_bytes(), token_mint.key().as_ref(), core_state.house_id.as_ref()],
+        bump
+    )]
+    pub mint_ledger: Box<Account<'info, MintLedger>>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
 
+#[derive(Accounts)]
+pub struct RemoveLiquidity<'info> {
+    #[account(
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.core_state_nonce,
+    )]
+    pub core_state: Box<Account<'info, CoreState>>,
+    #[account(mut)]
+    pub provider: Signer<'info>,
+    /// CHECK:
+    #[account(
+        mut,
+        seeds = [VAULT_AUTH_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.vault_auth_nonce,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    pub token_mint: Account<'info, Mint>,
+    /// CHECK:
+    #[account(mut)]
+    pub provider_token_account: UncheckedAccount<'info>,
+    /// CHECK:
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == mint_ledger.vault @ ErrorCode::InvalidVault,
+    )]
+    pub vault_token_account: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        constraint = lp_mint.key() == mint_ledger.lp_mint @ ErrorCode::InvalidTokenMint,
+    )]
+    pub lp_mint: Account<'info, Mint>,
+    #[account(
+        mut,
+        constraint = provider_lp_account.mint == lp_mint.key() @ ErrorCode::TokenMintMismatch,
+        constraint = provider_lp_account.owner == provider.key() @ ErrorCode::TokenOnwerMismatch,
+    )]
+    pub provider_lp_account: Account<'info, TokenAccount>,
+    #[account(
+        seeds = [MINT_LEDGER_SEED.as_bytes(), token_mint.key().as_ref(), core_state.house_id.as_ref()],
+        bump
+    )]
+    pub mint_ledger: Box<Account<'info, MintLedger>>,
     pub token_program: Program<'info, Token>,
     pub system_program: Program<'info, System>,
 }
@@ -609,7 +2568,7 @@ pub struct Withdraw<'info> {
 pub struct BetDirectly<'info> {
     #[account(
         mut,
-        seeds = [CORE_STATE_SEED.as_bytes(), core_state.admin.as_ref()],
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
         bump = core_state.core_state_nonce,
         constraint = core_state.active @ ErrorCode::NotActiveCoreState,
         constraint = core_state.allow_direct_bet @ ErrorCode::DirectBetNotAllowed,
@@ -620,7 +2579,7 @@ pub struct BetDirectly<'info> {
     /// CHECK:
     #[account(
         mut,
-        seeds = [VAULT_AUTH_SEED.as_bytes(), core_state.admin.as_ref()],
+        seeds = [VAULT_AUTH_SEED.as_bytes(), core_state.house_id.as_ref()],
         bump = core_state.vault_auth_nonce,
     )]
     pub vault_authority: AccountInfo<'info>,
@@ -629,23 +2588,60 @@ pub struct BetDirectly<'info> {
     #[account(mut)]
     pub user_token_account: UncheckedAccount<'info>,
     /// CHECK:
-    #[account(mut)]
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == mint_ledger.vault @ ErrorCode::InvalidVault,
+    )]
     pub vault_token_account: UncheckedAccount<'info>,
+    /// CHECK:
     #[account(
-        seeds = [ALLOWED.as_bytes(), token_mint.key().as_ref(), core_state.admin.key().as_ref()],
-        bump
+        mut,
+        constraint = fee_vault.key() == mint_ledger.fee_vault @ ErrorCode::InvalidFeeVault,
+    )]
+    pub fee_vault: UncheckedAccount<'info>,
+    #[account(
+        seeds = [ALLOWED.as_bytes(), token_mint.key().as_ref(), core_state.house_id.as_ref()],
+        bump,
+        constraint = allowed_bets.enabled @ ErrorCode::MintDisabled,
     )]
     pub allowed_bets: Account<'info, AllowedBets>,
+    #[account(
+        mut,
+        seeds = [MINT_LEDGER_SEED.as_bytes(), token_mint.key().as_ref(), core_state.house_id.as_ref()],
+        bump
+    )]
+    pub mint_ledger: Box<Account<'info, MintLedger>>,
+    #[account(
+        init_if_needed,
+        space = 8 + std::mem::size_of::<Pubkey>() + std::mem::size_of::<Pubkey>() + 8,
+        seeds = [USER_EXPOSURE_SEED.as_bytes(), token_mint.key().as_ref(), user.key().as_ref(), core_state.house_id.as_ref()],
+        bump,
+        payer = user,
+    )]
+    pub user_exposure: Box<Account<'info, UserExposure>>,
+    #[account(
+        init_if_needed,
+        space = 8 + std::mem::size_of::<Pubkey>() + 8 + 8 + 8 + 8 + 8 + 8 + 4 + MAX_PLAYER_STATS_MINTS * (std::mem::size_of::<Pubkey>() + 8 + 8) + 8 + 8,
+        seeds = [PLAYER_STATS_SEED.as_bytes(), user.key().as_ref(), core_state.house_id.as_ref()],
+        bump,
+        payer = user,
+    )]
+    pub player_stats: Box<Account<'info, PlayerStats>>,
+    /// CHECK:
+    #[account(address = slot_hashes::id())]
+    pub slot_hashes: UncheckedAccount<'info>,
+    /// CHECK:
+    #[account(address = instructions::id())]
+    pub instructions: UncheckedAccount<'info>,
     pub token_program: Program<'info, Token>,
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-#[instruction(args: BetArgs)]
 pub struct Bet<'info> {
     #[account(
         mut,
-        seeds = [CORE_STATE_SEED.as_bytes(), core_state.admin.as_ref()],
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
         bump = core_state.core_state_nonce,
         constraint = core_state.active @ ErrorCode::NotActiveCoreState,
     )]
@@ -655,7 +2651,7 @@ pub struct Bet<'info> {
     /// CHECK:
     #[account(
         mut,
-        seeds = [VAULT_AUTH_SEED.as_bytes(), core_state.admin.as_ref()],
+        seeds = [VAULT_AUTH_SEED.as_bytes(), core_state.house_id.as_ref()],
         bump = core_state.vault_auth_nonce,
     )]
     pub vault_authority: AccountInfo<'info>,
@@ -664,21 +2660,64 @@ pub struct Bet<'info> {
     #[account(mut)]
     pub user_token_account: UncheckedAccount<'info>,
     /// CHECK:
-    #[account(mut)]
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == mint_ledger.vault @ ErrorCode::InvalidVault,
+    )]
     pub vault_token_account: UncheckedAccount<'info>,
+    /// CHECK:
+    #[account(
+        mut,
+        constraint = fee_vault.key() == mint_ledger.fee_vault @ ErrorCode::InvalidFeeVault,
+    )]
+    pub fee_vault: UncheckedAccount<'info>,
     #[account(
         init,
-        space = 8 + 1 + 8 + 1 + 8 + 1 + 3 * std::mem::size_of::<Pubkey>(),
-        seeds = [BET_STATE_SEED.as_bytes(), core_state.admin.as_ref(), user.key().as_ref(), &core_state.flip_counter.to_le_bytes()],
+        space = 8 + 1 + 8 + 1 + 8 + 1 + 5 * std::mem::size_of::<Pubkey>() + 32 + 32 + 8 + 1 + 8 + 8 + 8 + 1 + 2 + 1 + 2 + 2 + 4 + std::mem::size_of::<Pubkey>() + 8 + 8 + 1 + 8,
+        seeds = [BET_STATE_SEED.as_bytes(), core_state.house_id.as_ref(), user.key().as_ref(), &core_state.flip_counter.to_le_bytes()],
         bump,
         payer = user,
     )]
     pub bet_state: Box<Account<'info, BetState>>,
     #[account(
-        seeds = [ALLOWED.as_bytes(), token_mint.key().as_ref(), core_state.admin.key().as_ref()],
-        bump
+        seeds = [ALLOWED.as_bytes(), token_mint.key().as_ref(), core_state.house_id.as_ref()],
+        bump,
+        constraint = allowed_bets.enabled @ ErrorCode::MintDisabled,
     )]
     pub allowed_bets: Account<'info, AllowedBets>,
+    /// CHECK: only read in CommitReveal mode, see place_bet. Houses settling with
+    /// other modes may pass any writable account and never need to create a queue.
+    #[account(mut)]
+    pub commitment_queue: AccountInfo<'info>,
+    #[account(
+        mut,
+        seeds = [MINT_LEDGER_SEED.as_bytes(), token_mint.key().as_ref(), core_state.house_id.as_ref()],
+        bump
+    )]
+    pub mint_ledger: Box<Account<'info, MintLedger>>,
+    #[account(
+        init_if_needed,
+        space = 8 + std::mem::size_of::<Pubkey>() + std::mem::size_of::<Pubkey>() + 8,
+        seeds = [USER_EXPOSURE_SEED.as_bytes(), token_mint.key().as_ref(), user.key().as_ref(), core_state.house_id.as_ref()],
+        bump,
+        payer = user,
+    )]
+    pub user_exposure: Box<Account<'info, UserExposure>>,
+    #[account(
+        init_if_needed,
+        space = 8 + std::mem::size_of::<Pubkey>() + 8 + 8 + 8 + 8 + 8 + 8 + 4 + MAX_PLAYER_STATS_MINTS * (std::mem::size_of::<Pubkey>() + 8 + 8) + 8 + 8,
+        seeds = [PLAYER_STATS_SEED.as_bytes(), user.key().as_ref(), core_state.house_id.as_ref()],
+        bump,
+        payer = user,
+    )]
+    pub player_stats: Box<Account<'info, PlayerStats>>,
+    /// CHECK:
+    #[account(
+        mut,
+        seeds = [RENT_SPONSOR_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump,
+    )]
+    pub rent_sponsor: AccountInfo<'info>,
     pub token_program: Program<'info, Token>,
     pub system_program: Program<'info, System>,
     pub rent: Sysvar<'info, Rent>,
@@ -687,26 +2726,95 @@ pub struct Bet<'info> {
 #[derive(Accounts)]
 pub struct BetReturn<'info> {
     #[account(
-        constraint = core_state.executer == executer.key() @ ErrorCode::WrongExecuter,
+        constraint = core_state.is_executer(&executer.key()) || bet_state.settles_without_executer() @ ErrorCode::WrongExecuter,
     )]
     pub executer: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.core_state_nonce,
+        constraint = core_state.active @ ErrorCode::NotActiveCoreState,
+    )]
+    pub core_state: Box<Account<'info, CoreState>>,
     /// CHECK:
     #[account(mut)]
-    pub admin: AccountInfo<'info>,
+    pub user: AccountInfo<'info>,
+    /// CHECK:
+    #[account(
+        mut,
+        seeds = [VAULT_AUTH_SEED.as_bytes(), core_state.house_id.as_ref()],
+        bump = core_state.vault_auth_nonce,
+    )]
+    pub vault_authority: AccountInfo<'info>,
+    pub token_mint: Account<'info, Mint>,
+    /// CHECK:
+    #[account(mut)]
+    pub user_token_account: UncheckedAccount<'info>,
+    /// CHECK:
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == mint_ledger.vault @ ErrorCode::InvalidVault,
+    )]
+    pub vault_token_account: UncheckedAccount<'info>,
+    /// CHECK:
+    #[account(
+        mut,
+        constraint = rent_payer.key() == bet_state.rent_payer @ ErrorCode::PublicKeyMismatch,
+    )]
+    pub rent_payer: AccountInfo<'info>,
+    #[account(
+        mut,
+        close = rent_payer,
+        constraint = bet_state.approved @ ErrorCode::UnapprovedBet,
+        constraint = bet_state.core_state == core_state.key() @ ErrorCode::InvalidCoreState,
+        constraint = bet_state.token_mint == token_mint.key() @ ErrorCode::InvalidTokenMint,
+        seeds = [BET_STATE_SEED.as_bytes(), core_state.house_id.as_ref(), user.key().as_ref(), &bet_state.flip_counter.to_le_bytes()],
+        bump = bet_state.bet_state_nonce,
+    )]
+    pub bet_state: Box<Account<'info, BetState>>,
+    #[account(
+        mut,
+        seeds = [MINT_LEDGER_SEED.as_bytes(), token_mint.key().as_ref(), core_state.house_id.as_ref()],
+        bump
+    )]
+    pub mint_ledger: Box<Account<'info, MintLedger>>,
+    #[account(
+        mut,
+        seeds = [USER_EXPOSURE_SEED.as_bytes(), token_mint.key().as_ref(), user.key().as_ref(), core_state.house_id.as_ref()],
+        bump,
+    )]
+    pub user_exposure: Box<Account<'info, UserExposure>>,
+    #[account(
+        mut,
+        seeds = [PLAYER_STATS_SEED.as_bytes(), user.key().as_ref(), core_state.house_id.as_ref()],
+        bump,
+    )]
+    pub player_stats: Box<Account<'info, PlayerStats>>,
+    /// CHECK:
+    #[account(address = slot_hashes::id())]
+    pub slot_hashes: UncheckedAccount<'info>,
+    /// CHECK:
+    #[account(address = instructions::id())]
+    pub instructions: UncheckedAccount<'info>,
+
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct RefundExpiredBet<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
     #[account(
-        mut,
-        seeds = [CORE_STATE_SEED.as_bytes(), core_state.admin.as_ref()],
+        seeds = [CORE_STATE_SEED.as_bytes(), core_state.house_id.as_ref()],
         bump = core_state.core_state_nonce,
-        constraint = core_state.active @ ErrorCode::NotActiveCoreState,
     )]
     pub core_state: Box<Account<'info, CoreState>>,
     /// CHECK:
-    #[account(mut)]
-    pub user: AccountInfo<'info>,
-    /// CHECK:
     #[account(
         mut,
-        seeds = [VAULT_AUTH_SEED.as_bytes(), core_state.admin.as_ref()],
+        seeds = [VAULT_AUTH_SEED.as_bytes(), core_state.house_id.as_ref()],
         bump = core_state.vault_auth_nonce,
     )]
     pub vault_authority: AccountInfo<'info>,
@@ -715,21 +2823,57 @@ pub struct BetReturn<'info> {
     #[account(mut)]
     pub user_token_account: UncheckedAccount<'info>,
     /// CHECK:
-    #[account(mut)]
+    #[account(
+        mut,
+        constraint = vault_token_account.key() == mint_ledger.vault @ ErrorCode::InvalidVault,
+    )]
     pub vault_token_account: UncheckedAccount<'info>,
+    /// CHECK:
+    #[account(
+        mut,
+        constraint = fee_vault.key() == mint_ledger.fee_vault @ ErrorCode::InvalidFeeVault,
+    )]
+    pub fee_vault: UncheckedAccount<'info>,
+    /// CHECK:
+    #[account(
+        mut,
+        constraint = rent_payer.key() == bet_state.rent_payer @ ErrorCode::PublicKeyMismatch,
+    )]
+    pub rent_payer: AccountInfo<'info>,
     #[account(
         mut,
+        close = rent_payer,
         constraint = bet_state.approved @ ErrorCode::UnapprovedBet,
+        constraint = bet_state.user == user.key() @ ErrorCode::PublicKeyMismatch,
         constraint = bet_state.core_state == core_state.key() @ ErrorCode::InvalidCoreState,
         constraint = bet_state.token_mint == token_mint.key() @ ErrorCode::InvalidTokenMint,
-        seeds = [BET_STATE_SEED.as_bytes(), core_state.admin.as_ref(), user.key().as_ref(), &bet_state.flip_counter.to_le_bytes()],
+        seeds = [BET_STATE_SEED.as_bytes(), core_state.house_id.as_ref(), user.key().as_ref(), &bet_state.flip_counter.to_le_bytes()],
         bump = bet_state.bet_state_nonce,
     )]
     pub bet_state: Box<Account<'info, BetState>>,
-
+    #[account(
+        mut,
+        seeds = [MINT_LEDGER_SEED.as_bytes(), token_mint.key().as_ref(), core_state.house_id.as_ref()],
+        bump
+    )]
+    pub mint_ledger: Box<Account<'info, MintLedger>>,
+    #[account(
+        mut,
+        seeds = [USER_EXPOSURE_SEED.as_bytes(), token_mint.key().as_ref(), user.key().as_ref(), core_state.house_id.as_ref()],
+        bump,
+    )]
+    pub user_exposure: Box<Account<'info, UserExposure>>,
+    #[account(
+        mut,
+        seeds = [PLAYER_STATS_SEED.as_bytes(), user.key().as_ref(), core_state.house_id.as_ref()],
+        bump,
+    )]
+    pub player_stats: Box<Account<'info, PlayerStats>>,
+    /// CHECK:
+    #[account(address = slot_hashes::id())]
+    pub slot_hashes: UncheckedAccount<'info>,
     pub token_program: Program<'info, Token>,
     pub system_program: Program<'info, System>,
-    pub rent: Sysvar<'info, Rent>,
 }
 
 // -------------------------------------------------------------------------------- //
@@ -738,23 +2882,117 @@ pub struct BetReturn<'info> {
 
 #[derive(AnchorSerialize, AnchorDeserialize)]
 pub struct InitializeArgs {
+    pub house_id: Pubkey, // seeds every house PDA, stays fixed across admin changes
     pub core_state_nonce: u8,
     pub vault_auth_nonce: u8,
     pub fee_percent: u16,
     pub win_ratio: u16, // 4500 => 45%
+    pub oracle: Pubkey,
+    pub randomness_mode: RandomnessMode,
+    pub payout_multiplier_bps: u32, // 19600 => 1.96x
+    pub max_rtp_bps: u16, // 9800 => 98%
+    pub dice_edge_bps: u16, // 200 => dice pays 98% of the fair odds
+    pub config_delay: i64, // seconds a proposed config waits before apply_config
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize)]
 pub struct UpdateCoreStateArgs {
     pub active: bool,
     pub allow_direct_bet: bool,
+    pub oracle: Pubkey,
+    pub randomness_mode: RandomnessMode,
+    pub settle_timeout: i64, // seconds before a pending bet can be refunded
+    pub admin_funded_rent: bool,
+    pub payout_multiplier_bps: u32,
+    pub max_rtp_bps: u16,
+    pub dice_edge_bps: u16,
+    pub referral_share_bps: u16, // share of each fee credited to the bettor's referrer
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct ProposeConfigArgs {
+    pub win_ratio: u16,
     pub fee_percent: u16,
+    pub config_delay: i64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct SetRolesArgs {
+    pub pauser: Pubkey,
+    pub treasurer: Pubkey,
+    pub risk_manager: Pubkey,
+    pub withdraw_destinations: Vec<Pubkey>, // at most MAX_WITHDRAW_DESTINATIONS
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct SetActiveArgs {
+    pub active: bool,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct ExecuterArgs {
+    pub executer: Pubkey,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct ProposeAdminArgs {
+    pub new_admin: Pubkey, // Pubkey::default() cancels a pending proposal
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize)]
 pub struct RegisterArgs {
     pub vault_token_account_nonce: u8,
-    pub amounts: Vec<u64>
+    pub amounts: Vec<u64>,
+    pub amount_range: Option<AmountRange>,
+    pub max_payout_bps: u16,
+    pub max_user_liability: u64,
+    pub max_window_volume: u64,
+    pub volume_window_slots: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct UpdateAllowedBetsArgs {
+    pub amounts: Vec<u64>,
+    pub amount_range: Option<AmountRange>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct SetMintEnabledArgs {
+    pub enabled: bool,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct ClaimFeesArgs {
+    pub amount: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct RegisterReferralArgs {
+    pub referrer: Pubkey,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct SetLoyaltyMintArgs {
+    pub loyalty_rate_bps: u16, // 10000 => one point per unit of fee paid
+    pub points_per_free_bet: u64, // 0 disables redeem_loyalty_points
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct RedeemLoyaltyPointsArgs {
+    pub free_bets: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct SetRiskLimitsArgs {
+    pub max_payout_bps: u16, // 0 => unlimited
+    pub max_user_liability: u64, // 0 => unlimited
+    pub max_window_volume: u64, // 0 => unlimited
+    pub volume_window_slots: u64, // 1 => per slot
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct PushCommitmentsArgs {
+    pub commitments: Vec<[u8; 32]>, // sha256(secret)
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize)]
@@ -767,11 +3005,17 @@ pub struct WithdrawArgs {
     pub amount: u64,
 }
 
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct RemoveLiquidityArgs {
+    pub shares: u64,
+}
+
 #[derive(AnchorSerialize, AnchorDeserialize)]
 pub struct BetDirectlyArgs {
     pub amount: u64,
     pub bet_side: bool, // true = Head, false = Tail
-    pub allowed_amounts_nonce: u8
+    pub allowed_amounts_nonce: u8,
+    pub referrer: Option<Pubkey>, // must match the user's ReferralLink
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize)]
@@ -780,7 +3024,130 @@ pub struct BetArgs {
     pub bet_side: bool, // true = Head, false = Tail
     pub flip_counter: u64,
     pub bet_state_nonce: u8,
-    pub allowed_nonce: u8
+    pub allowed_nonce: u8,
+    pub client_seed: [u8; 32],
+    pub referrer: Option<Pubkey>, // must match the user's ReferralLink
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct BetDiceArgs {
+    pub amount: u64,
+    pub target: u16, // 0..10000
+    pub roll_over: bool, // true = win on roll > target, false = win on roll < target
+    pub flip_counter: u64,
+    pub bet_state_nonce: u8,
+    pub allowed_nonce: u8,
+    pub client_seed: [u8; 32],
+    pub referrer: Option<Pubkey>, // must match the user's ReferralLink
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct BetReturnArgs {
+    pub secret: Option<[u8; 32]>, // preimage of bet_state.commitment, CommitReveal only
+}
+
+// -------------------------------------------------------------------------------- //
+// ------------------------------------ Events ------------------------------------ //
+// -------------------------------------------------------------------------------- //
+
+#[event]
+pub struct BetPlaced {
+    pub bet_state: Pubkey,
+    pub user: Pubkey,
+    pub token_mint: Pubkey,
+    pub flip_counter: u64,
+    pub game: Game,
+    pub randomness_mode: RandomnessMode,
+    pub client_seed: [u8; 32],
+    pub commitment: [u8; 32], // CommitReveal only
+    pub amount: u64,
+    pub fee: u64,
+    pub payout: u64, // paid if the bet wins
+}
+
+#[event]
+pub struct BetSettled {
+    pub bet_state: Pubkey, // default for bet_directly
+    pub user: Pubkey,
+    pub token_mint: Pubkey,
+    pub flip_counter: u64,
+    pub game: Game,
+    pub randomness_mode: RandomnessMode,
+    pub client_seed: [u8; 32],
+    pub entropy: [u8; 32], // see randomness::resolve_settlement
+    pub roll: u64, // calc_roll(entropy, client_seed, bet_state)
+    pub secret: Option<[u8; 32]>, // revealed secret, CommitReveal only
+    pub slot_hash: Option<[u8; 32]>, // hash of placed_slot, or of the latest slot for bet_directly
+    pub signature: Option<[u8; 64]>, // oracle signature, Ed25519Oracle only
+    pub is_win: bool,
+    pub amount: u64,
+    pub fee: u64,
+    pub paid_out: u64, // 0 on a loss
+}
+
+#[event]
+pub struct BetRefunded {
+    pub bet_state: Pubkey,
+    pub user: Pubkey,
+    pub token_mint: Pubkey,
+    pub flip_counter: u64,
+    pub amount: u64,
+    pub fee: u64, // refunded along with the amount
+}
+
+#[event]
+pub struct Deposited {
+    pub user: Pubkey, // treasurer
+    pub token_mint: Pubkey,
+    pub flip_counter: u64,
+    pub amount: u64,
+}
+
+#[event]
+pub struct Withdrawn {
+    pub user: Pubkey, // treasurer
+    pub token_mint: Pubkey,
+    pub flip_counter: u64,
+    pub destination: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct FeesClaimed {
+    pub user: Pubkey, // treasurer
+    pub token_mint: Pubkey,
+    pub flip_counter: u64,
+    pub destination: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct ReferralRewardsClaimed {
+    pub user: Pubkey, // referrer
+    pub token_mint: Pubkey,
+    pub flip_counter: u64,
+    pub amount: u64,
+}
+
+#[event]
+pub struct CoreStateUpdated {
+    pub core_state: Pubkey,
+    pub user: Pubkey, // admin, or the pauser for set_active
+    pub flip_counter: u64,
+    pub active: bool,
+    pub allow_direct_bet: bool,
+    pub fee_percent: u16,
+    pub win_ratio: u16,
+    pub payout_multiplier_bps: u32,
+    pub randomness_mode: RandomnessMode,
+}
+
+#[event]
+pub struct MintRegistered {
+    pub user: Pubkey, // admin
+    pub token_mint: Pubkey,
+    pub flip_counter: u64,
+    pub vault: Pubkey,
 }
 
 // -------------------------------------------------------------------------------- //
@@ -793,12 +3160,44 @@ pub struct CoreState {
     pub core_state_nonce: u8,
     pub vault_auth_nonce: u8,
     pub admin: Pubkey, // admin public key
-    pub executer: Pubkey, // executer public key
+    pub executers: Vec<Pubkey>, // settlement keys, at most MAX_EXECUTERS
     pub flip_counter: u64,
     pub fee_percent: u16, // 500 => 5 %
     pub win_ratio: u16, // 4500 => 45%
     pub active: bool,
     pub allow_direct_bet: bool,
+    pub oracle: Pubkey, // ed25519 signer for bet_return_vrf
+    pub randomness_mode: RandomnessMode,
+    pub settle_timeout: i64, // seconds after placement before refund_expired_bet is allowed, fixed per bet at placement
+    pub admin_funded_rent: bool, // bet_state rent is paid from the rent sponsor PDA
+    pub payout_multiplier_bps: u32, // 19600 => a win pays 1.96x the amount
+    pub max_rtp_bps: u16, // ceiling on win_ratio * payout_multiplier_bps / 10000
+    pub dice_edge_bps: u16, // house edge taken off the fair dice payout
+    pub house_id: Pubkey,
+    pub pending_admin: Pubkey, // proposed by the admin, becomes admin on accept_admin
+    pub pauser: Pubkey, // can only flip active
+    pub treasurer: Pubkey, // deposits, and withdraws to withdraw_destinations
+    pub risk_manager: Pubkey, // edits AllowedBets and risk limits
+    pub withdraw_destinations: Vec<Pubkey>, // owners vault funds may be sent to
+    pub config_delay: i64, // timelock on win_ratio / fee_percent changes, at least MIN_CONFIG_DELAY seconds
+    pub referral_share_bps: u16, // 2000 => referrers earn 20% of the fees they bring in
+    pub loyalty_mint: Pubkey, // minted by vault_authority, default while loyalty is off
+    pub loyalty_rate_bps: u16, // points earned per unit of fee paid
+    pub points_per_free_bet: u64, // points burned for one bet without a fee
+}
+
+impl CoreState {
+    pub fn is_executer(&self, key: &Pubkey) -> bool {
+        self.executers.contains(key)
+    }
+
+    pub fn assert_withdraw_destination(&self, destination: &Pubkey) -> Result<()> {
+        if self.withdraw_destinations.contains(destination) {
+            Ok(())
+        } else {
+            Err(ErrorCode::DestinationNotWhitelisted.into())
+        }
+    }
 }
 
 #[account]
@@ -812,6 +3211,275 @@ pub struct BetState {
     pub bet_side: bool, // true = Head, false = Tail
     pub flip_counter: u64,
     pub approved: bool, // originally false. set true after transfer
+    pub client_seed: [u8; 32], // chosen by the user, mixed into the outcome
+    pub commitment: [u8; 32], // executer's sha256(secret), revealed in bet_return
+    pub placed_slot: u64, // its slot hash is mixed into the outcome
+    pub oracle: Pubkey, // set when the bet is settled by bet_return_vrf
+    pub randomness_mode: RandomnessMode, // mode in effect when the bet was placed
+    pub payout: u64, // paid on win, counted in MintLedger.pending_liability until settled
+    pub fee: u64,
+    pub placed_at: i64,
+    pub expires_at: i64, // placed_at + CoreState.settle_timeout, refundable from then on
+    pub rent_payer: Pubkey, // user, or the rent sponsor; gets the rent back on close
+    pub game: Game,
+    pub dice_target: u16, // Dice only, 0..10000
+    pub dice_roll_over: bool, // Dice only, true = win on roll > target, false = roll < target
+    pub win_ratio: u16, // CoinFlip only, CoreState.win_ratio when the bet was placed
+    pub fee_percent: u16, // CoreState.fee_percent when the bet was placed, charged as fee
+    pub payout_multiplier_bps: u32, // payout = amount * payout_multiplier_bps / 10000
+    pub referrer: Pubkey, // default without a referrer
+    pub referral_reward: u64, // credited to the referrer when the bet settles
+    pub loyalty_points: u64, // credited to PlayerStats when the bet settles
+    pub free_fee_bet: bool, // placed without a fee, given back on refund
+}
+
+impl BetState {
+    pub fn is_win(&self, roll: u64) -> bool {
+        match self.game {
+            Game::CoinFlip => randomness::is_win(self.bet_side, roll, self.win_ratio),
+            Game::Dice => randomness::is_dice_win(self.dice_target, self.dice_roll_over, roll),
+        }
+    }
+
+    /// SlotHash outcomes are public once the slot is hashed, and an expired CommitReveal
+    /// bet pays out without a reveal, so neither has to wait for an executer.
+    pub fn settles_without_executer(&self) -> bool {
+        match self.randomness_mode {
+            RandomnessMode::SlotHash => true,
+            RandomnessMode::CommitReveal => Clock::get()
+                .map(|clock| clock.unix_timestamp >= self.expires_at)
+                .unwrap_or(false),
+            RandomnessMode::LegacyHash | RandomnessMode::Ed25519Oracle => false,
+        }
+    }
+}
+
+#[account]
+#[derive(Default)]
+pub struct MintLedger {
+    pub token_mint: Pubkey,
+    pub vault: Pubkey, // vault_token_account, or vault_authority for native
+    pub total_wagered: u64,
+    pub total_paid_out: u64,
+    pub total_fees: u64,
+    pub pending_bets: u64,
+    pub pending_liability: u64, // sum of pending BetState payouts
+    pub lp_mint: Pubkey, // set by init_lp_pool, default while the vault is the admin's alone
+    pub window_start_slot: u64,
+    pub window_volume: u64, // wagered since window_start_slot
+    pub fee_vault: Pubkey, // fee vault token account, or the native fee vault PDA
+    pub fee_vault_nonce: u8, // bump of the native fee vault PDA
+    pub claimed_fees: u64, // fee vault balance is total_fees - claimed_fees
+    pub referral_owed: u64, // accrued referral rewards not yet claimed, held in the fee vault
+    pub pending_fees: u64, // fees of pending bets, held in the fee vault until they settle
+    pub legacy_core_state: Pubkey, // set by migrate_vault, whose pending bets refund_legacy_bet pays back
+}
+
+impl MintLedger {
+    pub fn record_bet(&mut self, amount: u64, fee: u64, payout: u64) -> Result<()> {
+        self.total_wagered = self.total_wagered.checked_add(amount).ok_or(ErrorCode::NumericalOverflow)?;
+        self.total_fees = self.total_fees.checked_add(fee).ok_or(ErrorCode::NumericalOverflow)?;
+        self.pending_bets = self.pending_bets.checked_add(1).ok_or(ErrorCode::NumericalOverflow)?;
+        self.pending_liability = self.pending_liability.checked_add(payout).ok_or(ErrorCode::NumericalOverflow)?;
+        self.pending_fees = self.pending_fees.checked_add(fee).ok_or(ErrorCode::NumericalOverflow)?;
+        Ok(())
+    }
+
+    pub fn record_settlement(&mut self, fee: u64, payout: u64, paid_out: u64) -> Result<()> {
+        self.pending_bets = self.pending_bets.checked_sub(1).ok_or(ErrorCode::NumericalOverflow)?;
+        self.pending_fees = self.pending_fees.checked_sub(fee).ok_or(ErrorCode::NumericalOverflow)?;
+        self.pending_liability = self.pending_liability.checked_sub(payout).ok_or(ErrorCode::NumericalOverflow)?;
+        self.total_paid_out = self.total_paid_out.checked_add(paid_out).ok_or(ErrorCode::NumericalOverflow)?;
+        Ok(())
+    }
+
+    pub fn record_refund(&mut self, amount: u64, fee: u64, payout: u64) -> Result<()> {
+        self.total_wagered = self.total_wagered.checked_sub(amount).ok_or(ErrorCode::NumericalOverflow)?;
+        self.total_fees = self.total_fees.checked_sub(fee).ok_or(ErrorCode::NumericalOverflow)?;
+        self.pending_fees = self.pending_fees.checked_sub(fee).ok_or(ErrorCode::NumericalOverflow)?;
+        self.pending_bets = self.pending_bets.checked_sub(1).ok_or(ErrorCode::NumericalOverflow)?;
+        self.pending_liability = self.pending_liability.checked_sub(payout).ok_or(ErrorCode::NumericalOverflow)?;
+        Ok(())
+    }
+
+    pub fn record_direct_bet(&mut self, amount: u64, fee: u64, paid_out: u64) -> Result<()> {
+        self.total_wagered = self.total_wagered.checked_add(amount).ok_or(ErrorCode::NumericalOverflow)?;
+        self.total_fees = self.total_fees.checked_add(fee).ok_or(ErrorCode::NumericalOverflow)?;
+        self.total_paid_out = self.total_paid_out.checked_add(paid_out).ok_or(ErrorCode::NumericalOverflow)?;
+        Ok(())
+    }
+
+    pub fn record_fee_claim(&mut self, amount: u64) -> Result<()> {
+        self.claimed_fees = self.claimed_fees.checked_add(amount).ok_or(ErrorCode::NumericalOverflow)?;
+        Ok(())
+    }
+
+    pub fn record_referral_reward(&mut self, reward: u64) -> Result<()> {
+        self.referral_owed = self.referral_owed.checked_add(reward).ok_or(ErrorCode::NumericalOverflow)?;
+        Ok(())
+    }
+
+    pub fn record_referral_claim(&mut self, amount: u64) -> Result<()> {
+        self.referral_owed = self.referral_owed.checked_sub(amount).ok_or(ErrorCode::NumericalOverflow)?;
+        self.record_fee_claim(amount)
+    }
+
+    pub fn record_volume(&mut self, slot: u64, amount: u64, allowed_bets: &AllowedBets) -> Result<()> {
+        let window_slots = allowed_bets.volume_window_slots.max(1);
+        let window_start_slot = slot - slot % window_slots;
+        if window_start_slot != self.window_start_slot {
+            self.window_start_slot = window_start_slot;
+            self.window_volume = 0;
+        }
+
+        self.window_volume = self.window_volume.checked_add(amount).ok_or(ErrorCode::NumericalOverflow)?;
+        if allowed_bets.max_window_volume > 0 && self.window_volume > allowed_bets.max_window_volume {
+            return Err(ErrorCode::VolumeLimitExceeded.into());
+        }
+        Ok(())
+    }
+}
+
+#[account]
+#[derive(Default)]
+pub struct PlayerStats {
+    pub user: Pubkey,
+    pub total_bets: u64, // settled bets, refunds are not counted
+    pub wins: u64,
+    pub losses: u64,
+    pub current_streak: i64, // +n after n wins in a row, -n after n losses in a row
+    pub longest_win_streak: u64,
+    pub last_bet_slot: u64, // slot the most recently settled bet was placed in
+    pub mints: Vec<MintStats>, // at most MAX_PLAYER_STATS_MINTS, later mints are not tracked
+    pub loyalty_points: u64, // earned, not yet minted by claim_loyalty_points
+    pub free_fee_bets: u64, // bought with loyalty points, used up one per bet
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct MintStats {
+    pub token_mint: Pubkey,
+    pub total_wagered: u64,
+    pub total_paid_out: u64,
+}
+
+impl PlayerStats {
+    /// The fee for a bet of `amount` and the loyalty points it earns. Uses up a free bet
+    /// if the player has one. Points are credited by the caller once the bet settles.
+    pub fn charge_fee(&mut self, amount: u64, core_state: &CoreState) -> Result<(u64, u64)> {
+        if self.free_fee_bets > 0 {
+            self.free_fee_bets -= 1;
+            return Ok((0, 0));
+        }
+
+        let fee = amount * (core_state.fee_percent as u64) / 10000;
+        let points = if core_state.loyalty_mint != Pubkey::default() {
+            (fee as u128 * core_state.loyalty_rate_bps as u128 / 10000) as u64
+        } else {
+            0
+        };
+        Ok((fee, points))
+    }
+
+    pub fn credit_loyalty_points(&mut self, points: u64) -> Result<()> {
+        self.loyalty_points = self.loyalty_points.checked_add(points).ok_or(ErrorCode::NumericalOverflow)?;
+        Ok(())
+    }
+
+    /// Gives back the free bet a refunded bet used up.
+    pub fn restore_free_fee_bet(&mut self) -> Result<()> {
+        self.free_fee_bets = self.free_fee_bets.checked_add(1).ok_or(ErrorCode::NumericalOverflow)?;
+        Ok(())
+    }
+
+    pub fn record(&mut self, token_mint: &Pubkey, amount: u64, paid_out: u64, slot: u64) -> Result<()> {
+        self.total_bets = self.total_bets.checked_add(1).ok_or(ErrorCode::NumericalOverflow)?;
+        if paid_out > 0 {
+            self.wins = self.wins.checked_add(1).ok_or(ErrorCode::NumericalOverflow)?;
+            self.current_streak = if self.current_streak > 0 { self.current_streak + 1 } else { 1 };
+            self.longest_win_streak = self.longest_win_streak.max(self.current_streak as u64);
+        } else {
+            self.losses = self.losses.checked_add(1).ok_or(ErrorCode::NumericalOverflow)?;
+            self.current_streak = if self.current_streak < 0 { self.current_streak - 1 } else { -1 };
+        }
+        self.last_bet_slot = self.last_bet_slot.max(slot);
+
+        let index = match self.mints.iter().position(|m| m.token_mint == *token_mint) {
+            Some(index) => index,
+            None if self.mints.len() < MAX_PLAYER_STATS_MINTS => {
+                self.mints.push(MintStats { token_mint: *token_mint, ..Default::default() });
+                self.mints.len() - 1
+            }
+            None => return Ok(()),
+        };
+        let mint_stats = &mut self.mints[index];
+        mint_stats.total_wagered = mint_stats.total_wagered.checked_add(amount).ok_or(ErrorCode::NumericalOverflow)?;
+        mint_stats.total_paid_out = mint_stats.total_paid_out.checked_add(paid_out).ok_or(ErrorCode::NumericalOverflow)?;
+        Ok(())
+    }
+}
+
+#[account]
+#[derive(Default)]
+pub struct ReferralLink {
+    pub core_state: Pubkey,
+    pub user: Pubkey,
+    pub referrer: Pubkey, // set once by register_referral
+}
+
+#[account]
+#[derive(Default)]
+pub struct Referrer {
+    pub core_state: Pubkey,
+    pub referrer: Pubkey,
+    pub token_mint: Pubkey,
+    pub accrued: u64, // claimable through claim_referral_rewards
+    pub total_earned: u64,
+}
+
+#[account]
+#[derive(Default)]
+pub struct UserExposure {
+    pub user: Pubkey,
+    pub token_mint: Pubkey,
+    pub pending_liability: u64, // sum of the user's pending BetState payouts
+}
+
+impl UserExposure {
+    pub fn assert_within(&self, payout: u64, max_user_liability: u64) -> Result<()> {
+        let liability = self.pending_liability.checked_add(payout).ok_or(ErrorCode::NumericalOverflow)?;
+        if max_user_liability > 0 && liability > max_user_liability {
+            return Err(ErrorCode::UserExposureExceeded.into());
+        }
+        Ok(())
+    }
+
+    pub fn record_bet(&mut self, payout: u64, max_user_liability: u64) -> Result<()> {
+        self.assert_within(payout, max_user_liability)?;
+        self.pending_liability = self.pending_liability.checked_add(payout).ok_or(ErrorCode::NumericalOverflow)?;
+        Ok(())
+    }
+
+    pub fn release(&mut self, payout: u64) -> Result<()> {
+        self.pending_liability = self.pending_liability.checked_sub(payout).ok_or(ErrorCode::NumericalOverflow)?;
+        Ok(())
+    }
+}
+
+#[account]
+#[derive(Default)]
+pub struct PendingConfig {
+    pub core_state: Pubkey,
+    pub win_ratio: u16,
+    pub fee_percent: u16,
+    pub config_delay: i64,
+    pub effective_at: i64, // unix timestamp from which apply_config succeeds
+}
+
+#[account]
+#[derive(Default)]
+pub struct CommitmentQueue {
+    pub core_state: Pubkey,
+    pub commitments: Vec<[u8; 32]>, // consumed oldest first by bet
 }
 
 #[account]
@@ -819,7 +3487,26 @@ pub struct BetState {
 pub struct AllowedBets {
     pub nonce: u8,
     pub token_mint: Pubkey,
-    pub amounts: Vec<u64>
+    pub amounts: Vec<u64>,
+    pub max_payout_bps: u16, // single payout, of the vault balance net of pending payouts
+    pub max_user_liability: u64, // pending payouts per user
+    pub max_window_volume: u64, // wagered per volume window
+    pub volume_window_slots: u64,
+    pub enabled: bool, // bets are rejected while false
+    pub amount_range: Option<AmountRange>, // when set, used instead of amounts
+}
+
+impl AllowedBets {
+    pub fn space(amounts: usize) -> usize {
+        8 + 1 + std::mem::size_of::<Pubkey>() + 4 + amounts * 8 + 2 + 8 + 8 + 8 + 1 + 1 + 8 + 8 + 8
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct AmountRange {
+    pub min: u64,
+    pub max: u64,
+    pub step: u64, // accepted amounts are multiples of step within [min, max]
 }
 
 #[error_code]
@@ -853,5 +3540,107 @@ pub enum ErrorCode {
     #[msg("Amount not allowed")]
     AmountNotAllowed,
     #[msg("Wrong Executer")]
-    WrongExecuter
+    WrongExecuter,
+    #[msg("Commitment Queue Full")]
+    CommitmentQueueFull,
+    #[msg("Commitment Queue Empty")]
+    EmptyCommitmentQueue,
+    #[msg("Revealed secret does not match commitment")]
+    InvalidReveal,
+    #[msg("Slot Hash Not Found")]
+    SlotHashNotFound,
+    #[msg("Wrong Settlement Instruction")]
+    WrongSettlement,
+    #[msg("Invalid Oracle Signature")]
+    InvalidOracleSignature,
+    #[msg("Oracle Not Set")]
+    OracleNotSet,
+    #[msg("Randomness mode does not support this bet")]
+    RandomnessModeUnsupported,
+    #[msg("Invalid Vault")]
+    InvalidVault,
+    #[msg("Insufficient Vault Liquidity")]
+    InsufficientVaultLiquidity,
+    #[msg("Invalid Settle Timeout")]
+    InvalidSettleTimeout,
+    #[msg("Bet Not Expired")]
+    BetNotExpired,
+    #[msg("Invalid Payout Multiplier")]
+    InvalidPayoutMultiplier,
+    #[msg("RTP Ceiling Exceeded")]
+    RtpCeilingExceeded,
+    #[msg("Invalid Dice Target")]
+    InvalidDiceTarget,
+    #[msg("Liquidity Pool Open")]
+    LiquidityPoolOpen,
+    #[msg("Zero Shares")]
+    ZeroShares,
+    #[msg("Payout Limit Exceeded")]
+    PayoutLimitExceeded,
+    #[msg("User Exposure Exceeded")]
+    UserExposureExceeded,
+    #[msg("Volume Limit Exceeded")]
+    VolumeLimitExceeded,
+    #[msg("Mint Disabled")]
+    MintDisabled,
+    #[msg("Pending Bets Exist")]
+    PendingBetsExist,
+    #[msg("Invalid Amount Range")]
+    InvalidAmountRange,
+    #[msg("Not Pending Admin")]
+    NotPendingAdmin,
+    #[msg("Invalid Legacy State")]
+    InvalidLegacyState,
+    #[msg("Executer Already Added")]
+    ExecuterAlreadyAdded,
+    #[msg("Executer List Full")]
+    ExecuterListFull,
+    #[msg("Executer Not Found")]
+    ExecuterNotFound,
+    #[msg("Wrong Pauser")]
+    WrongPauser,
+    #[msg("Wrong Treasurer")]
+    WrongTreasurer,
+    #[msg("Wrong Risk Manager")]
+    WrongRiskManager,
+    #[msg("Destination Not Whitelisted")]
+    DestinationNotWhitelisted,
+    #[msg("Too Many Destinations")]
+    TooManyDestinations,
+    #[msg("Invalid Config Delay")]
+    InvalidConfigDelay,
+    #[msg("Config Not Ready")]
+    ConfigNotReady,
+    #[msg("Invalid Fee Vault")]
+    InvalidFeeVault,
+    #[msg("Insufficient Fees")]
+    InsufficientFees,
+    #[msg("Invalid Referral Share")]
+    InvalidReferralShare,
+    #[msg("Invalid Referrer")]
+    InvalidReferrer,
+    #[msg("Referrer Mismatch")]
+    ReferrerMismatch,
+    #[msg("Missing Referral Accounts")]
+    MissingReferralAccounts,
+    #[msg("Nothing To Claim")]
+    NothingToClaim,
+    #[msg("Invalid Loyalty Mint")]
+    InvalidLoyaltyMint,
+    #[msg("Loyalty Not Enabled")]
+    LoyaltyNotEnabled,
+    #[msg("Invalid Win Ratio")]
+    InvalidWinRatio,
+    #[msg("Invalid RTP Ceiling")]
+    InvalidRtpCeiling,
+    #[msg("Invalid Fee Percent")]
+    InvalidFeePercent,
+    #[msg("Referral Rewards Owed")]
+    ReferralRewardsOwed,
+    #[msg("Invalid Loyalty Rate")]
+    InvalidLoyaltyRate,
+    #[msg("Slot Hash Available")]
+    SlotHashAvailable,
+    #[msg("Direct Bet Not Last Instruction")]
+    DirectBetNotLastInstruction,
 }
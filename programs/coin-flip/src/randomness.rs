@@ -0,0 +1,177 @@
+use anchor_lang::{
+    prelude::*,
+    solana_program::hash::{hash, hashv},
+};
+use crate::{utils, BetState, ErrorCode};
+
+pub const ROLL_RANGE: u64 = 10000;
+pub const MIN_DICE_CHANCE: u64 = 100; // 1%
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum RandomnessMode {
+    LegacyHash, // clock and flip counter, predictable
+    SlotHash, // SlotHashes sysvar contents
+    #[default]
+    CommitReveal, // executer secret committed before the bet
+    // oracle signature checked through the Instructions sysvar. The runtime accepts any
+    // valid signature, and an oracle can make many over the same message, so it can grind
+    // the outcome: this mode trusts the oracle the way CommitReveal trusts the executer.
+    Ed25519Oracle,
+}
+
+pub fn validate_mode(mode: RandomnessMode, oracle: &Pubkey) -> Result<()> {
+    if mode == RandomnessMode::Ed25519Oracle && *oracle == Pubkey::default() {
+        return Err(ErrorCode::OracleNotSet.into());
+    }
+    Ok(())
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Game {
+    #[default]
+    CoinFlip, // bet_side against win_ratio
+    Dice, // roll over/under a target
+}
+
+/// The roll plus the mode-specific entropy it was derived from, so that an off-chain
+/// verifier can recompute the flip. The raw inputs are set only in the modes that use
+/// them, so the commitment and the oracle signature can be checked after the bet_state
+/// is closed.
+#[derive(Clone, Copy, Default)]
+pub struct Outcome {
+    pub entropy: [u8; 32],
+    pub roll: u64,
+    pub secret: Option<[u8; 32]>, // CommitReveal
+    pub slot_hash: Option<[u8; 32]>, // SlotHash, CommitReveal
+    pub signature: Option<[u8; 64]>, // Ed25519Oracle
+}
+
+/// Roll for bet_directly, which has to resolve in the same transaction.
+pub fn resolve_direct(mode: RandomnessMode, slot_hashes: &AccountInfo, flip_counter: u64) -> Result<Outcome> {
+    let clock = (Clock::get()?).unix_timestamp as u64;
+
+    let mut outcome = Outcome::default();
+    outcome.entropy = match mode {
+        RandomnessMode::LegacyHash => calc_hash(&[0u8; 32], clock, flip_counter),
+        RandomnessMode::SlotHash => {
+            let slot_hash = utils::most_recent_slot_hash(slot_hashes)?;
+            outcome.slot_hash = Some(slot_hash);
+            calc_hash(&slot_hash, clock, flip_counter)
+        }
+        RandomnessMode::CommitReveal | RandomnessMode::Ed25519Oracle => {
+            return Err(ErrorCode::RandomnessModeUnsupported.into());
+        }
+    };
+    outcome.roll = hash_to_u64(&outcome.entropy) % ROLL_RANGE;
+
+    Ok(outcome)
+}
+
+/// Roll for a pending bet, using the mode the bet was placed under. Every mode's
+/// entropy is mixed with the player's client seed and the bet_state key.
+pub fn resolve_settlement(
+    bet_state: &BetState,
+    bet_state_key: &Pubkey,
+    slot_hashes: &AccountInfo,
+    instructions: &AccountInfo,
+    secret: Option<[u8; 32]>,
+) -> Result<Outcome> {
+    let mut outcome = Outcome::default();
+    outcome.entropy = match bet_state.randomness_mode {
+        RandomnessMode::LegacyHash => {
+            let clock = (Clock::get()?).unix_timestamp as u64;
+            calc_hash(&[0u8; 32], clock, bet_state.flip_counter)
+        }
+        RandomnessMode::SlotHash => {
+            // the slot the bet landed in was hashed after the bet was placed, and cannot
+            // be changed by choosing when to settle
+            let slot_hash = utils::slot_hash_at(slot_hashes, bet_state.placed_slot)?;
+            outcome.slot_hash = Some(slot_hash);
+            slot_hash
+        }
+        RandomnessMode::CommitReveal => {
+            let secret = secret.ok_or(ErrorCode::InvalidReveal)?;
+            if hash(&secret).to_bytes() != bet_state.commitment {
+                return Err(ErrorCode::InvalidReveal.into());
+            }
+            let slot_hash = utils::slot_hash_at(slot_hashes, bet_state.placed_slot)?;
+            outcome.secret = Some(secret);
+            outcome.slot_hash = Some(slot_hash);
+            hashv(&[&secret, &slot_hash]).to_bytes()
+        }
+        RandomnessMode::Ed25519Oracle => {
+            // the preceding Ed25519 program instruction must carry the oracle's signature
+            // over (bet_state, flip_counter, client_seed). Ed25519 signatures are not
+            // unique, so this only proves the oracle chose the outcome, not that it was fair
+            let message = [
+                bet_state_key.as_ref(),
+                &bet_state.flip_counter.to_le_bytes(),
+                &bet_state.client_seed,
+            ].concat();
+            let signature = utils::verify_ed25519_instruction(instructions, &bet_state.oracle, &message)?;
+            outcome.signature = Some(signature);
+            hashv(&[&signature]).to_bytes()
+        }
+    };
+    outcome.roll = calc_roll(&outcome.entropy, &bet_state.client_seed, bet_state_key);
+
+    Ok(outcome)
+}
+
+pub fn is_win(bet_side: bool, roll: u64, win_ratio: u16) -> bool {
+    (bet_side && roll < win_ratio as u64) ||
+        (!bet_side && roll >= ROLL_RANGE - win_ratio as u64)
+}
+
+/// Number of winning rolls out of ROLL_RANGE.
+pub fn dice_win_chance(target: u16, roll_over: bool) -> u64 {
+    let target = (target as u64).min(ROLL_RANGE - 1);
+    if roll_over {
+        ROLL_RANGE - 1 - target
+    } else {
+        target
+    }
+}
+
+/// Fair odds `ROLL_RANGE / chance`, less the house edge, in basis points.
+pub fn dice_multiplier_bps(target: u16, roll_over: bool, dice_edge_bps: u16) -> Result<u32> {
+    if target as u64 >= ROLL_RANGE {
+        return Err(ErrorCode::InvalidDiceTarget.into());
+    }
+    let chance = dice_win_chance(target, roll_over);
+    if chance < MIN_DICE_CHANCE {
+        return Err(ErrorCode::InvalidDiceTarget.into());
+    }
+
+    let multiplier_bps = (10000 - dice_edge_bps.min(10000) as u64) * ROLL_RANGE / chance;
+    // a win has to pay more than the amount back
+    if multiplier_bps <= 10000 {
+        return Err(ErrorCode::InvalidDiceTarget.into());
+    }
+    Ok(multiplier_bps as u32)
+}
+
+pub fn is_dice_win(target: u16, roll_over: bool, roll: u64) -> bool {
+    if roll_over {
+        roll > target as u64
+    } else {
+        roll < target as u64
+    }
+}
+
+pub fn calc_hash(slot_hash: &[u8; 32], clock: u64, flip_counter: u64) -> [u8; 32] {
+    hashv(&[slot_hash, &clock.to_le_bytes(), &flip_counter.to_le_bytes()]).to_bytes()
+}
+
+pub fn calc_roll(entropy: &[u8; 32], client_seed: &[u8; 32], bet_state: &Pubkey) -> u64 {
+    let hash = hashv(&[entropy, client_seed, bet_state.as_ref()]).to_bytes();
+
+    hash_to_u64(&hash) % ROLL_RANGE
+}
+
+fn hash_to_u64(hash: &[u8; 32]) -> u64 {
+    let mut head = [0u8; 8];
+    head.copy_from_slice(&hash[..8]);
+
+    u64::from_le_bytes(head)
+}
@@ -1,28 +1,453 @@
 use anchor_lang::{
     prelude::*,
     solana_program::{
-        program::invoke_signed,
+        program::{invoke, invoke_signed},
         program_option::COption,
         program_pack::{IsInitialized, Pack},
         system_instruction,
+        ed25519_program,
+        sysvar::instructions::{load_current_index_checked, load_instruction_at_checked},
     },
 };
 use spl_associated_token_account::get_associated_token_address;
 use anchor_spl::token::{Mint, Token, TokenAccount};
 use spl_token::{instruction::initialize_account2, state::Account as SplAccount};
-use crate::{ErrorCode};
+use anchor_lang::Discriminator;
+use crate::{randomness::ROLL_RANGE, AllowedBets, AmountRange, BetState, CoreState, ErrorCode, FEE_VAULT_SEED, VAULT_AUTH_SEED};
+use std::convert::TryInto;
 
 pub fn assert_allowed_amount(
-    allowed_amounts: &Vec<u64>,
+    allowed_bets: &AllowedBets,
     amount: u64
 ) -> Result<()> {
-    if allowed_amounts.contains(&amount) {
+    let allowed = match allowed_bets.amount_range {
+        Some(range) => amount >= range.min && amount <= range.max && amount.is_multiple_of(range.step),
+        None => allowed_bets.amounts.contains(&amount),
+    };
+    if allowed {
         Ok(())
     } else {
         Err(ErrorCode::AmountNotAllowed.into())
     }
 }
 
+pub fn assert_valid_amount_range(amount_range: &Option<AmountRange>) -> Result<()> {
+    match amount_range {
+        Some(range) if range.step == 0 || range.min == 0 || range.min > range.max => {
+            Err(ErrorCode::InvalidAmountRange.into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Amount paid on a win, `amount * payout_multiplier_bps / 10000`.
+pub fn calc_payout(amount: u64, payout_multiplier_bps: u32) -> Result<u64> {
+    let payout = (amount as u128)
+        .checked_mul(payout_multiplier_bps as u128)
+        .ok_or(ErrorCode::NumericalOverflow)?
+        / 10000;
+    payout.try_into().map_err(|_| ErrorCode::NumericalOverflow.into())
+}
+
+/// A win has to pay at least the amount back, and the expected return of a bet,
+/// `win_ratio * payout_multiplier_bps / 10000`, must stay within the admin's ceiling.
+/// Neither the win_ratio nor the ceiling can exceed 100%.
+pub fn assert_rtp(win_ratio: u16, payout_multiplier_bps: u32, max_rtp_bps: u16) -> Result<()> {
+    if win_ratio as u64 > ROLL_RANGE {
+        return Err(ErrorCode::InvalidWinRatio.into());
+    }
+    if max_rtp_bps > 10000 {
+        return Err(ErrorCode::InvalidRtpCeiling.into());
+    }
+    if payout_multiplier_bps < 10000 {
+        return Err(ErrorCode::InvalidPayoutMultiplier.into());
+    }
+    let rtp_bps = (win_ratio as u64)
+        .checked_mul(payout_multiplier_bps as u64)
+        .ok_or(ErrorCode::NumericalOverflow)?
+        / 10000;
+    if rtp_bps > max_rtp_bps as u64 {
+        Err(ErrorCode::RtpCeilingExceeded.into())
+    } else {
+        Ok(())
+    }
+}
+
+pub fn assert_dice_edge(dice_edge_bps: u16, max_rtp_bps: u16) -> Result<()> {
+    if 10000u16.saturating_sub(dice_edge_bps) > max_rtp_bps {
+        Err(ErrorCode::RtpCeilingExceeded.into())
+    } else {
+        Ok(())
+    }
+}
+
+/// LP shares for depositing `amount` into a pool worth `pool_value` with `supply`
+/// shares outstanding, rounded down.
+pub fn calc_lp_shares(amount: u64, pool_value: u64, supply: u64) -> Result<u64> {
+    let shares = (amount as u128)
+        .checked_mul(supply as u128 + crate::LP_VIRTUAL_SHARES as u128)
+        .ok_or(ErrorCode::NumericalOverflow)?
+        / (pool_value as u128 + crate::LP_VIRTUAL_ASSETS as u128);
+    shares.try_into().map_err(|_| ErrorCode::NumericalOverflow.into())
+}
+
+/// Amount paid for burning `shares`, rounded down.
+pub fn calc_lp_redemption(shares: u64, pool_value: u64, supply: u64) -> Result<u64> {
+    let amount = (shares as u128)
+        .checked_mul(pool_value as u128 + crate::LP_VIRTUAL_ASSETS as u128)
+        .ok_or(ErrorCode::NumericalOverflow)?
+        / (supply as u128 + crate::LP_VIRTUAL_SHARES as u128);
+    amount.try_into().map_err(|_| ErrorCode::NumericalOverflow.into())
+}
+
+// SlotHashes layout: [len: u64][(slot: u64, hash: [u8; 32]); len], newest first
+const SLOT_HASH_ENTRY_LEN: usize = 8 + 32;
+
+pub fn most_recent_slot_hash(slot_hashes: &AccountInfo) -> Result<[u8; 32]> {
+    let data = slot_hashes.try_borrow_data()?;
+    if data.len() < 8 + SLOT_HASH_ENTRY_LEN {
+        return Err(ErrorCode::SlotHashNotFound.into());
+    }
+
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&data[16..48]);
+    Ok(hash)
+}
+
+pub fn slot_hash_at(slot_hashes: &AccountInfo, slot: u64) -> Result<[u8; 32]> {
+    let data = slot_hashes.try_borrow_data()?;
+    if data.len() < 8 {
+        return Err(ErrorCode::SlotHashNotFound.into());
+    }
+
+    let len = u64::from_le_bytes(data[..8].try_into().unwrap()) as usize;
+    for i in 0..len {
+        let start = 8 + i * SLOT_HASH_ENTRY_LEN;
+        if data.len() < start + SLOT_HASH_ENTRY_LEN {
+            break;
+        }
+        let entry_slot = u64::from_le_bytes(data[start..start + 8].try_into().unwrap());
+        if entry_slot == slot {
+            let mut hash = [0u8; 32];
+            hash.copy_from_slice(&data[start + 8..start + SLOT_HASH_ENTRY_LEN]);
+            return Ok(hash);
+        }
+        if entry_slot < slot {
+            break;
+        }
+    }
+    Err(ErrorCode::SlotHashNotFound.into())
+}
+
+/// Checks that the current instruction is the transaction's last, and was not invoked
+/// through CPI. An instruction resolved in the same transaction must not be followed by
+/// anything that can inspect the outcome and abort a lost bet.
+pub fn assert_last_top_level_instruction(instructions: &AccountInfo) -> Result<()> {
+    let current_index = load_current_index_checked(instructions)? as usize;
+    let current = load_instruction_at_checked(current_index, instructions)?;
+    if current.program_id != crate::id()
+        || load_instruction_at_checked(current_index + 1, instructions).is_ok()
+    {
+        return Err(ErrorCode::DirectBetNotLastInstruction.into());
+    }
+    Ok(())
+}
+
+// Ed25519SignatureOffsets, following the [num_signatures: u8][padding: u8] header
+const ED25519_OFFSETS_START: usize = 2;
+const ED25519_OFFSETS_LEN: usize = 14;
+
+/// Checks that the instruction right before the current one is an Ed25519 program
+/// instruction verifying `signer`'s signature over `message`, and returns the signature.
+pub fn verify_ed25519_instruction(
+    instructions: &AccountInfo,
+    signer: &Pubkey,
+    message: &[u8],
+) -> Result<[u8; 64]> {
+    let current_index = load_current_index_checked(instructions)?;
+    if current_index == 0 {
+        return Err(ErrorCode::InvalidOracleSignature.into());
+    }
+    let ix = load_instruction_at_checked((current_index - 1) as usize, instructions)?;
+    if ix.program_id != ed25519_program::id() {
+        return Err(ErrorCode::InvalidOracleSignature.into());
+    }
+
+    let data = &ix.data;
+    if data.len() < ED25519_OFFSETS_START + ED25519_OFFSETS_LEN || data[0] != 1 {
+        return Err(ErrorCode::InvalidOracleSignature.into());
+    }
+    let read_u16 = |at: usize| u16::from_le_bytes([data[ED25519_OFFSETS_START + at], data[ED25519_OFFSETS_START + at + 1]]);
+    let signature_offset = read_u16(0) as usize;
+    let signature_ix_index = read_u16(2);
+    let public_key_offset = read_u16(4) as usize;
+    let public_key_ix_index = read_u16(6);
+    let message_offset = read_u16(8) as usize;
+    let message_size = read_u16(10) as usize;
+    let message_ix_index = read_u16(12);
+
+    // all data must live inside the Ed25519 instruction itself
+    if signature_ix_index != u16::MAX || public_key_ix_index != u16::MAX || message_ix_index != u16::MAX {
+        return Err(ErrorCode::InvalidOracleSignature.into());
+    }
+
+    let public_key = data.get(public_key_offset..public_key_offset + 32);
+    let signed_message = data.get(message_offset..message_offset + message_size);
+    let signature = data.get(signature_offset..signature_offset + 64);
+    match (public_key, signed_message, signature) {
+        (Some(public_key), Some(signed_message), Some(signature))
+            if public_key == signer.as_ref() && signed_message == message =>
+        {
+            let mut result = [0u8; 64];
+            result.copy_from_slice(signature);
+            Ok(result)
+        }
+        _ => Err(ErrorCode::InvalidOracleSignature.into()),
+    }
+}
+
+pub fn assert_max_payout(vault_balance: u64, pending_liability: u64, payout: u64, max_payout_bps: u16) -> Result<()> {
+    if max_payout_bps == 0 {
+        return Ok(());
+    }
+    let max_payout = (vault_balance.saturating_sub(pending_liability) as u128) * (max_payout_bps as u128) / 10000;
+    if payout as u128 > max_payout {
+        Err(ErrorCode::PayoutLimitExceeded.into())
+    } else {
+        Ok(())
+    }
+}
+
+/// Balance the vault can pay out: token amount, or lamports above the rent-exempt
+/// minimum of the native vault_authority.
+pub fn vault_balance(vault: &AccountInfo, is_native: bool) -> Result<u64> {
+    if is_native {
+        let rent_reserve = Rent::get()?.minimum_balance(0);
+        Ok(vault.lamports().saturating_sub(rent_reserve))
+    } else {
+        let vault_account: SplAccount = assert_initialized(vault)?;
+        Ok(vault_account.amount)
+    }
+}
+
+pub fn assert_solvent(vault_balance: u64, pending_liability: u64, payout: u64) -> Result<()> {
+    if vault_balance.saturating_sub(pending_liability) < payout {
+        Err(ErrorCode::InsufficientVaultLiquidity.into())
+    } else {
+        Ok(())
+    }
+}
+
+/// Pays `amount` out of the vault, as SPL tokens or as lamports of the native
+/// vault_authority.
+#[allow(clippy::too_many_arguments)]
+pub fn transfer_from_vault<'info>(
+    vault: &AccountInfo<'info>,
+    destination: &AccountInfo<'info>,
+    vault_authority: &AccountInfo<'info>,
+    token_program: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    vault_auth_seeds: &[&[u8]],
+    amount: u64,
+    is_native: bool,
+) -> Result<()> {
+    if !is_native {
+        invoke_signed(
+            &spl_token::instruction::transfer(
+                token_program.key,
+                vault.key,
+                destination.key,
+                vault_authority.key,
+                &[],
+                amount,
+            )?,
+            &[
+                vault.clone(),
+                destination.clone(),
+                token_program.clone(),
+                vault_authority.clone(),
+            ],
+            &[vault_auth_seeds],
+        )?;
+    } else {
+        assert_keys_equal(*vault.key, *vault_authority.key)?;
+        invoke_signed(
+            &system_instruction::transfer(vault.key, destination.key, amount),
+            &[
+                vault.clone(),
+                destination.clone(),
+                system_program.clone(),
+            ],
+            &[vault_auth_seeds],
+        )?;
+    }
+    Ok(())
+}
+
+/// Pays `amount` out of a fee vault. SPL fee vaults are owned by vault_authority, the
+/// native fee vault signs for itself.
+#[allow(clippy::too_many_arguments)]
+pub fn transfer_from_fee_vault<'info>(
+    fee_vault: &AccountInfo<'info>,
+    destination: &AccountInfo<'info>,
+    vault_authority: &AccountInfo<'info>,
+    token_program: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    core_state: &CoreState,
+    fee_vault_nonce: u8,
+    amount: u64,
+    is_native: bool,
+) -> Result<()> {
+    if is_native {
+        let fee_vault_seeds = [
+            FEE_VAULT_SEED.as_bytes(),
+            core_state.house_id.as_ref(),
+            &[fee_vault_nonce],
+        ];
+        transfer_from_vault(fee_vault, destination, fee_vault, token_program, system_program, &fee_vault_seeds, amount, true)
+    } else {
+        let vault_auth_seeds = [
+            VAULT_AUTH_SEED.as_bytes(),
+            core_state.house_id.as_ref(),
+            &[core_state.vault_auth_nonce],
+        ];
+        transfer_from_vault(fee_vault, destination, vault_authority, token_program, system_program, &vault_auth_seeds, amount, false)
+    }
+}
+
+/// Pays `amount` into the vault from an account owned by `owner`, as SPL tokens or
+/// as lamports of the native vault_authority.
+pub fn transfer_to_vault<'info>(
+    source: &AccountInfo<'info>,
+    vault: &AccountInfo<'info>,
+    owner: &AccountInfo<'info>,
+    token_program: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    amount: u64,
+    is_native: bool,
+) -> Result<()> {
+    if !is_native {
+        invoke(
+            &spl_token::instruction::transfer(
+                token_program.key,
+                source.key,
+                vault.key,
+                owner.key,
+                &[],
+                amount,
+            )?,
+            &[
+                source.clone(),
+                vault.clone(),
+                token_program.clone(),
+                owner.clone(),
+            ],
+        )?;
+    } else {
+        invoke(
+            &system_instruction::transfer(source.key, vault.key, amount),
+            &[
+                source.clone(),
+                vault.clone(),
+                system_program.clone(),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+/// Resizes a program-owned account, topping its rent up from `payer` or handing the
+/// surplus back to it.
+pub fn resize_account<'info>(
+    account: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    new_len: usize,
+) -> Result<()> {
+    let rent_exempt = Rent::get()?.minimum_balance(new_len);
+    let lamports = account.lamports();
+
+    if rent_exempt > lamports {
+        invoke(
+            &system_instruction::transfer(payer.key, account.key, rent_exempt - lamports),
+            &[
+                payer.clone(),
+                account.clone(),
+                system_program.clone(),
+            ],
+        )?;
+    } else if lamports > rent_exempt {
+        **account.try_borrow_mut_lamports()? -= lamports - rent_exempt;
+        **payer.try_borrow_mut_lamports()? += lamports - rent_exempt;
+    }
+    // solana-program 1.8 has no AccountInfo::realloc. Do what it does in later versions:
+    // rewrite the serialized length in front of the data and reslice it. The loader
+    // leaves MAX_PERMITTED_DATA_INCREASE bytes of zeroed space after every account.
+    let mut data = account.try_borrow_mut_data()?;
+    unsafe {
+        let data_ptr = data.as_mut_ptr();
+        *(data_ptr.offset(-8) as *mut u64) = new_len as u64;
+        *data = std::slice::from_raw_parts_mut(data_ptr, new_len);
+    }
+    Ok(())
+}
+
+// CoreState as it was laid out before house_id seeds
+const LEGACY_CORE_STATE_LEN: usize = 8 + 1 + 1 + 8 + 2 + 2 + 1 + 1 + 32 + 32;
+
+/// Checks that `account` is a CoreState from before house_id seeds, administered by
+/// `admin`.
+pub fn assert_legacy_core_state(account: &AccountInfo, admin: &Pubkey) -> Result<()> {
+    assert_owned_by(account, &crate::id())?;
+    let data = account.try_borrow_data()?;
+    if data.len() != LEGACY_CORE_STATE_LEN
+        || data[..8] != CoreState::discriminator()
+        || &data[10..42] != admin.as_ref()
+    {
+        return Err(ErrorCode::InvalidLegacyState.into());
+    }
+    Ok(())
+}
+
+// BetState as it was laid out before house_id seeds
+const LEGACY_BET_STATE_LEN: usize = 8 + 1 + 32 + 32 + 32 + 8 + 1 + 8 + 1;
+
+/// Checks that `account` is an unsettled BetState from before house_id seeds, placed by
+/// `user` in `token_mint` under `legacy_core_state`, and returns its amount and flip counter.
+pub fn load_legacy_bet_state(
+    account: &AccountInfo,
+    legacy_core_state: &Pubkey,
+    user: &Pubkey,
+    token_mint: &Pubkey,
+) -> Result<(u64, u64)> {
+    assert_owned_by(account, &crate::id())?;
+    let data = account.try_borrow_data()?;
+    if data.len() != LEGACY_BET_STATE_LEN
+        || data[..8] != BetState::discriminator()
+        || &data[9..41] != legacy_core_state.as_ref()
+        || &data[41..73] != user.as_ref()
+        || &data[73..105] != token_mint.as_ref()
+        || data[122] == 0
+    {
+        return Err(ErrorCode::InvalidLegacyState.into());
+    }
+    let amount = u64::from_le_bytes(data[105..113].try_into().unwrap());
+    let flip_counter = u64::from_le_bytes(data[114..122].try_into().unwrap());
+    Ok((amount, flip_counter))
+}
+
+/// Closes a program-owned account that is not deserialized through Anchor, handing its
+/// lamports to `destination`.
+pub fn close_account(account: &AccountInfo, destination: &AccountInfo) -> Result<()> {
+    let lamports = account.lamports();
+    **account.try_borrow_mut_lamports()? = 0;
+    **destination.try_borrow_mut_lamports()? = destination.lamports()
+        .checked_add(lamports)
+        .ok_or(ErrorCode::NumericalOverflow)?;
+    account.try_borrow_mut_data()?.fill(0);
+    Ok(())
+}
+
 pub fn assert_is_ata(
     ata: &AccountInfo,
     wallet: &Pubkey,
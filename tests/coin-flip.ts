@@ -1,12 +1,13 @@
 import * as anchor from '@project-serum/anchor';
 import { Program } from '@project-serum/anchor';
 import { CoinFlip } from '../target/types/coin_flip';
-import { Keypair, SystemProgram } from '@solana/web3.js';
-import { createAssociatedTokenAccount, createMint, getAssociatedTokenAddress, getOrCreateAssociatedTokenAccount, mintTo, NATIVE_MINT, TOKEN_PROGRAM_ID } from '@solana/spl-token';
+import { Keypair, LAMPORTS_PER_SOL, PublicKey, SystemProgram } from '@solana/web3.js';
+import { createAssociatedTokenAccount, createMint, getAssociatedTokenAddress, getMint, getOrCreateAssociatedTokenAccount, mintTo, NATIVE_MINT, TOKEN_PROGRAM_ID } from '@solana/spl-token';
 import { expect } from "chai";
 import * as assert from 'assert';
 import * as chai from 'chai';
 import chaiAsPromised from 'chai-as-promised';
+import { createHash, randomBytes } from 'crypto';
 import {
   initialize,
   register,
@@ -15,10 +16,60 @@ import {
   betDirectly,
   bet,
   betReturn,
-  updateCoreState
+  betReturnVrf,
+  betDice,
+  betDiceReturn,
+  initLpPool,
+  addLiquidity,
+  removeLiquidity,
+  setRiskLimits,
+  updateAllowedBets,
+  setMintEnabled,
+  deregisterMint,
+  proposeAdmin,
+  addExecuter,
+  removeExecuter,
+  acceptAdmin,
+  setRoles,
+  setActive,
+  proposeConfig,
+  applyConfig,
+  claimFees,
+  registerReferral,
+  initReferrer,
+  claimReferralRewards,
+  setLoyaltyMint,
+  claimLoyaltyPoints,
+  redeemLoyaltyPoints,
+  NO_RISK_LIMITS,
+  MIN_CONFIG_DELAY,
+  migrateVault,
+  refundLegacyBet,
+  settleExpiredBet,
+  refundExpiredBet,
+  updateCoreState,
+  initCommitmentQueue,
+  pushCommitments,
+  RandomnessMode
 } from './coin-flip_instruction';
 import {
-  getVaultTokenAccount
+  LEGACY_ADMIN,
+  LEGACY_USER,
+  LEGACY_MINT,
+  LEGACY_BET_STATE_SOL,
+  LEGACY_BET_STATE_SPL,
+  LEGACY_BET_AMOUNT_SOL,
+  LEGACY_BET_AMOUNT_SPL
+} from './coin-flip_fixtures';
+import {
+  getAllowed,
+  getVaultAuth,
+  getVaultTokenAccount,
+  getMintLedger,
+  getRentSponsor,
+  getNativeFeeVault,
+  getPlayerStats,
+  getPendingConfig
 } from './coin-flip_pda';
 
 chai.use(chaiAsPromised);
@@ -48,6 +99,26 @@ describe('coin-flip', () => {
   let coreStateAddress;
   const amounts = [1, 5_000_000 , 3, 4, 5];
 
+  // secrets known to the executer, by commitment
+  const secrets = new Map<string, Buffer>();
+
+  async function commitSecret() {
+    const secret = randomBytes(32);
+    const commitment = createHash('sha256').update(secret).digest();
+    await pushCommitments(admin.publicKey, executer, [commitment]);
+    secrets.set(commitment.toString('hex'), secret);
+  }
+
+  async function revealSecret(betState) {
+    const { commitment } = await program.account.betState.fetch(betState);
+    return secrets.get(Buffer.from(commitment).toString('hex'));
+  }
+
+  // oracle bets fall back to a refund once they expire
+  async function placeRefundableBets(config = {}) {
+    await updateCoreState(admin, { randomnessMode: RandomnessMode.Ed25519Oracle, oracle: executer.publicKey, settleTimeout: 1, ...config });
+  }
+
   it('Is initialized!', async () => {
     // airdrop to admin account
     await program.provider.connection.confirmTransaction(
@@ -80,6 +151,8 @@ describe('coin-flip', () => {
     vaultAuth = vaultAuthority;
     console.log("Core State: ", coreState.toBase58(), await program.account.coreState.fetch(coreStateAddress));
     console.log("Vault Authority: ", vaultAuthority.toBase58());
+
+    await initCommitmentQueue(admin);
   });
 
   it('Deposit Sol', async () => {
@@ -108,6 +181,42 @@ describe('coin-flip', () => {
     await register(admin, tokenMint, amounts);
   });
 
+  it('Failed Bet Spl without liquidity', async () => {
+    const emptyMint = await createMint(
+      provider.connection,
+      admin,
+      tokenMintAuthority.publicKey,
+      tokenMintAuthority.publicKey,
+      9
+    );
+    await register(admin, emptyMint, amounts);
+
+    await program.provider.connection.confirmTransaction(
+      await program.provider.connection.requestAirdrop(
+        user.publicKey,
+        AIRDROP_AMOUNT
+      ),
+      "confirmed"
+    );
+    const emptyMintUserAccount = await createAssociatedTokenAccount(
+      provider.connection,
+      user,
+      emptyMint,
+      user.publicKey
+    );
+    await mintTo(
+      provider.connection,
+      user,
+      emptyMint,
+      emptyMintUserAccount,
+      tokenMintAuthority,
+      DEPOSIT_AMOUNT
+    );
+
+    await commitSecret();
+    await expect(bet(admin.publicKey, user, emptyMint, BET_AMOUNT, true, randomBytes(32))).to.be.rejectedWith("Insufficient Vault Liquidity");
+  });
+
   it('Deposit Spl', async () => {
     // create admin token account
     adminTokenAccount = await createAssociatedTokenAccount(
@@ -170,15 +279,25 @@ describe('coin-flip', () => {
 
     const balanceBefore = await provider.connection.getBalance(user.publicKey);
 
-    let betState = await bet(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT, true);
+    await commitSecret();
+    let betState = await bet(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32));
 
     const balanceAfter = await provider.connection.getBalance(user.publicKey);
 
 
-    await expect(betReturn(admin, false_executer, betState)).to.be.rejectedWith("Wrong Executer");
+    await expect(betReturn(admin, false_executer, betState, await revealSecret(betState))).to.be.rejectedWith("Wrong Executer");
+    await expect(betReturn(admin, executer, betState, randomBytes(32))).to.be.rejectedWith("Revealed secret does not match commitment");
 
     const balanceFinal = await provider.connection.getBalance(user.publicKey);
     console.log("Should fail", {balanceBefore, balanceAfter, balanceFinal, result: balanceBefore > balanceFinal ? "lose" : "win"});
+
+    // the unsettled bet's payout cannot be withdrawn
+    const vaultBalance = await provider.connection.getBalance(vaultAuth);
+    await expect(withdraw(admin, NATIVE_MINT, vaultBalance)).to.be.rejectedWith("Insufficient Vault Liquidity");
+    const [mintLedger] = await getMintLedger(program.programId, NATIVE_MINT, admin.publicKey);
+    const ledger = await program.account.mintLedger.fetch(mintLedger);
+    expect(ledger.pendingBets.toNumber()).to.equal(1);
+    expect(ledger.pendingLiability.toNumber()).to.equal(2 * BET_AMOUNT);
   });
 
 
@@ -195,12 +314,13 @@ describe('coin-flip', () => {
     for (let i = 0; i < 10; i++) {
       const balanceBefore = await provider.connection.getBalance(user.publicKey);
   
-      let betState = await bet(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT, (i % 2) === 0);
+      await commitSecret();
+      let betState = await bet(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT, (i % 2) === 0, randomBytes(32));
       let betStateFetch = (await program.account.betState.fetch(betState));
 
       const balanceAfter = await provider.connection.getBalance(user.publicKey);
       
-      await betReturn(admin, executer, betState);
+      await betReturn(admin, executer, betState, await revealSecret(betState));
 
       const balanceFinal = await provider.connection.getBalance(user.publicKey);
       console.log("try", i + 1, {balanceBefore, balanceAfter, balanceFinal, result: balanceBefore > balanceFinal ? "lose" : "win"});
@@ -218,7 +338,7 @@ describe('coin-flip', () => {
         "confirmed"
     );
 
-    await expect(bet(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT + 1, (BET_AMOUNT % 2) === 0)).to.be.rejectedWith("Amount not allowed");
+    await expect(bet(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT + 1, (BET_AMOUNT % 2) === 0, randomBytes(32))).to.be.rejectedWith("Amount not allowed");
   });
 
   it('Failed BetDirectly Sol', async () => {
@@ -231,7 +351,9 @@ describe('coin-flip', () => {
         "confirmed"
     );
 
+    await updateCoreState(admin, { randomnessMode: RandomnessMode.SlotHash });
     await expect(betDirectly(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT + 1, (BET_AMOUNT % 2) === 0)).to.be.rejectedWith("Amount not allowed");
+    await updateCoreState(admin);
   });
 
 
@@ -256,12 +378,13 @@ describe('coin-flip', () => {
     for (let i = 0; i < 10; i++) {
       const balanceBefore = parseInt((await provider.connection.getTokenAccountBalance(userTokenAccount)).value.amount);
 
-      let betState = await bet(admin.publicKey, user, tokenMint, BET_AMOUNT, (i % 2) === 0);
+      await commitSecret();
+      let betState = await bet(admin.publicKey, user, tokenMint, BET_AMOUNT, (i % 2) === 0, randomBytes(32));
       let betStateFetch = (await program.account.betState.fetch(betState));
       
       const balanceAfter = parseInt((await provider.connection.getTokenAccountBalance(userTokenAccount)).value.amount);
       
-      await betReturn(admin, executer, betState);
+      await betReturn(admin, executer, betState, await revealSecret(betState));
 
       const balanceFinal = parseInt((await provider.connection.getTokenAccountBalance(userTokenAccount)).value.amount);
       console.log("try", i + 1, {balanceBefore, balanceAfter, balanceFinal, result: balanceBefore > balanceFinal ? "lose" : "win"});
@@ -269,6 +392,12 @@ describe('coin-flip', () => {
   });
 
   it('Bet Sol Directly', async () => {
+    await expect(betDirectly(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT, true)).to.be.rejectedWith("Randomness mode does not support this bet");
+    await updateCoreState(admin, { randomnessMode: RandomnessMode.SlotHash });
+    // nothing may follow the bet in its transaction, or a lost bet could be aborted
+    const followUp = SystemProgram.transfer({ fromPubkey: user.publicKey, toPubkey: user.publicKey, lamports: 0 });
+    await expect(betDirectly(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT, true, null, [followUp])).to.be.rejectedWith("Direct Bet Not Last Instruction");
+
     for (let i = 0; i < 10; i++) {
       const balanceBefore = await provider.connection.getBalance(user.publicKey);
   
@@ -290,9 +419,628 @@ describe('coin-flip', () => {
     }
   });
 
+  it('Bet Sol settled by oracle', async () => {
+    await expect(updateCoreState(admin, { randomnessMode: RandomnessMode.Ed25519Oracle })).to.be.rejectedWith("Oracle Not Set");
+    await updateCoreState(admin, { randomnessMode: RandomnessMode.Ed25519Oracle, oracle: executer.publicKey });
+
+    let betState = await bet(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32));
+    await expect(betReturn(admin, executer, betState, randomBytes(32))).to.be.rejectedWith("Wrong Settlement Instruction");
+    await expect(betReturnVrf(admin, executer, false_executer, betState)).to.be.rejectedWith("Invalid Oracle Signature");
+
+    const balanceBefore = await provider.connection.getBalance(user.publicKey);
+    await betReturnVrf(admin, executer, executer, betState);
+    const balanceFinal = await provider.connection.getBalance(user.publicKey);
+    console.log({balanceBefore, balanceFinal, result: balanceBefore < balanceFinal ? "win" : "lose"});
+
+    await updateCoreState(admin);
+  });
+
+  it('Bet Sol settled with slot hash', async () => {
+    await updateCoreState(admin, { randomnessMode: RandomnessMode.SlotHash });
+
+    let betState = await bet(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32));
+    await betReturn(admin, executer, betState);
+  });
+
+  it('Refund expired Spl bet', async () => {
+    await placeRefundableBets({ settleTimeout: 3600 });
+    const pendingBet = await bet(admin.publicKey, user, tokenMint, BET_AMOUNT, true, randomBytes(32));
+    await expect(refundExpiredBet(admin.publicKey, user, pendingBet)).to.be.rejectedWith("Bet Not Expired");
+
+    // the timeout is fixed when a bet is placed, so lowering it does not expire pending bets
+    await placeRefundableBets();
+    const betState = await bet(admin.publicKey, user, tokenMint, BET_AMOUNT, true, randomBytes(32));
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    await expect(refundExpiredBet(admin.publicKey, user, pendingBet)).to.be.rejectedWith("Bet Not Expired");
+    await betReturnVrf(admin, executer, executer, pendingBet);
+
+    const { amount, fee } = await program.account.betState.fetch(betState);
+    const balanceBefore = parseInt((await provider.connection.getTokenAccountBalance(userTokenAccount)).value.amount);
+    await refundExpiredBet(admin.publicKey, user, betState);
+    const balanceAfter = parseInt((await provider.connection.getTokenAccountBalance(userTokenAccount)).value.amount);
+    expect(balanceAfter - balanceBefore).to.equal(amount.toNumber() + fee.toNumber());
+    expect(await provider.connection.getAccountInfo(betState)).to.be.null;
+
+    await updateCoreState(admin);
+  });
+
+  it('Expired bets Sol', async () => {
+    const house = admin.publicKey;
+
+    // an unrevealed CommitReveal bet is paid out, never refunded
+    await commitSecret();
+    const pendingBet = await bet(house, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32));
+    await expect(settleExpiredBet(house, user, pendingBet)).to.be.rejectedWith("Wrong Executer");
+    await expect(settleExpiredBet(house, executer, pendingBet)).to.be.rejectedWith("Bet Not Expired");
+    await betReturn(admin, executer, pendingBet, await revealSecret(pendingBet));
+
+    await updateCoreState(admin, { settleTimeout: 1 });
+    await commitSecret();
+    const unrevealedBet = await bet(house, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32));
+    const { payout } = await program.account.betState.fetch(unrevealedBet);
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    await expect(refundExpiredBet(house, user, unrevealedBet)).to.be.rejectedWith("Wrong Settlement Instruction");
+    const balanceBefore = await provider.connection.getBalance(user.publicKey);
+    await settleExpiredBet(house, user, unrevealedBet);
+    // the user also pays the transaction fee
+    expect(await provider.connection.getBalance(user.publicKey)).to.be.greaterThan(balanceBefore + payout.toNumber() - 10000);
+
+    // anyone can settle a SlotHash bet while its slot hash is in the sysvar
+    await updateCoreState(admin, { randomnessMode: RandomnessMode.SlotHash, settleTimeout: 1 });
+    const slotHashBet = await bet(house, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32));
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    await expect(refundExpiredBet(house, user, slotHashBet)).to.be.rejectedWith("Slot Hash Available");
+    await betReturn(admin, user, slotHashBet);
+
+    // past the 512 slot SlotHashes window the outcome is gone, so the bet is refunded
+    const staleBet = await bet(house, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32));
+    const { placedSlot } = await program.account.betState.fetch(staleBet);
+    while (await provider.connection.getSlot() < placedSlot.toNumber() + 520) {
+      await new Promise(resolve => setTimeout(resolve, 2000));
+    }
+    await expect(betReturn(admin, executer, staleBet)).to.be.rejectedWith("Slot Hash Not Found");
+    await refundExpiredBet(house, user, staleBet);
+    expect(await provider.connection.getAccountInfo(staleBet)).to.be.null;
+
+    await updateCoreState(admin);
+  });
+
+  it('Bet Sol with admin-funded rent', async () => {
+    const [rentSponsor] = await getRentSponsor(program.programId, admin.publicKey);
+    await provider.connection.confirmTransaction(
+      await provider.connection.requestAirdrop(rentSponsor, LAMPORTS_PER_SOL),
+      "confirmed"
+    );
+    await updateCoreState(admin, { adminFundedRent: true });
+
+    await commitSecret();
+    const sponsorBefore = await provider.connection.getBalance(rentSponsor);
+    let betState = await bet(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32));
+    const { rentPayer } = await program.account.betState.fetch(betState);
+    expect(rentPayer.toBase58()).to.equal(rentSponsor.toBase58());
+
+    await betReturn(admin, executer, betState, await revealSecret(betState));
+    expect(await provider.connection.getBalance(rentSponsor)).to.equal(sponsorBefore);
+
+    await updateCoreState(admin);
+  });
+
+  it('Bet Sol with custom payout multiplier', async () => {
+    await expect(updateCoreState(admin, { payoutMultiplier: 2.5, maxRtpPercent: 100 })).to.be.rejectedWith("RTP Ceiling Exceeded");
+    await expect(updateCoreState(admin, { payoutMultiplier: 0.9 })).to.be.rejectedWith("Invalid Payout Multiplier");
+    await expect(updateCoreState(admin, { payoutMultiplier: 1.5, maxRtpPercent: 101 })).to.be.rejectedWith("Invalid RTP Ceiling");
+    await updateCoreState(admin, { payoutMultiplier: 1.96, maxRtpPercent: 98 });
+
+    await commitSecret();
+    let betState = await bet(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32));
+    const { payout, payoutMultiplierBps, feePercent } = await program.account.betState.fetch(betState);
+    expect(payout.toNumber()).to.equal(BET_AMOUNT * 196 / 100);
+    expect(payoutMultiplierBps).to.equal(19600);
+    expect(feePercent).to.equal(FEE_PERCENT * 100);
+
+    // the bet settles on the snapshot taken when it was placed, not on the reset 2x;
+    // place bets under 1.96x until one wins
+    let paidOut = 0;
+    for (let i = 0; i < 20 && paidOut === 0; i++) {
+      if (i > 0) {
+        await updateCoreState(admin, { payoutMultiplier: 1.96, maxRtpPercent: 98 });
+        await commitSecret();
+        betState = await bet(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32));
+      }
+      await updateCoreState(admin);
+      // the user gets the payout plus the bet_state rent back
+      const rent = (await provider.connection.getAccountInfo(betState)).lamports;
+      const balanceBefore = await provider.connection.getBalance(user.publicKey);
+      await betReturn(admin, executer, betState, await revealSecret(betState));
+      paidOut = await provider.connection.getBalance(user.publicKey) - balanceBefore - rent;
+      expect([0, BET_AMOUNT * 196 / 100]).to.include(paidOut);
+    }
+    expect(paidOut).to.equal(BET_AMOUNT * 196 / 100);
+  });
+
+  it('Bet Dice Sol', async () => {
+    await expect(betDice(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT, 50, false, randomBytes(32))).to.be.rejectedWith("Invalid Dice Target");
+
+    await commitSecret();
+    // roll under 2500 wins 25% of the time and pays 4x less the 2% edge
+    let betState = await betDice(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT, 2500, false, randomBytes(32));
+    const { payout, diceTarget, diceRollOver } = await program.account.betState.fetch(betState);
+    expect(payout.toNumber()).to.equal(BET_AMOUNT * 392 / 100);
+    expect(diceTarget).to.equal(2500);
+    expect(diceRollOver).to.equal(false);
+
+    await expect(betReturn(admin, executer, betState, await revealSecret(betState))).to.be.rejectedWith("Wrong Settlement Instruction");
+    await betDiceReturn(admin, executer, betState, await revealSecret(betState));
+    expect(await provider.connection.getAccountInfo(betState)).to.be.null;
+  });
+
+  it('Liquidity pool Spl', async () => {
+    const poolMint = await createMint(
+      provider.connection,
+      admin,
+      tokenMintAuthority.publicKey,
+      tokenMintAuthority.publicKey,
+      9
+    );
+    await register(admin, poolMint, amounts);
+    const userPoolAccount = await createAssociatedTokenAccount(
+      provider.connection,
+      user,
+      poolMint,
+      user.publicKey
+    );
+    await mintTo(
+      provider.connection,
+      user,
+      poolMint,
+      userPoolAccount,
+      tokenMintAuthority,
+      DEPOSIT_AMOUNT
+    );
+
+    const lpMint = await initLpPool(admin, poolMint);
+    await expect(withdraw(admin, poolMint, 0)).to.be.rejectedWith("Liquidity Pool Open");
+
+    const userLpAccount = await createAssociatedTokenAccount(
+      provider.connection,
+      user,
+      lpMint,
+      user.publicKey
+    );
+    await addLiquidity(admin.publicKey, user, poolMint, DEPOSIT_AMOUNT);
+    const shares = parseInt((await provider.connection.getTokenAccountBalance(userLpAccount)).value.amount);
+    // the pool starts out at one share per base unit
+    expect(shares).to.equal(DEPOSIT_AMOUNT);
+
+    await removeLiquidity(admin.publicKey, user, poolMint, shares / 2);
+    const balance = parseInt((await provider.connection.getTokenAccountBalance(userPoolAccount)).value.amount);
+    // rounding always favours the pool
+    expect(balance).to.be.at.most(DEPOSIT_AMOUNT / 2);
+    expect(balance).to.be.at.least(DEPOSIT_AMOUNT / 2 - 1);
+
+    // a deposit while a bet is pending is priced as if the bet loses
+    await commitSecret();
+    const betState = await bet(admin.publicKey, user, poolMint, BET_AMOUNT, true, randomBytes(32));
+    const [poolVault] = await getVaultTokenAccount(program.programId, poolMint, admin.publicKey);
+    const vaultBalance = parseInt((await provider.connection.getTokenAccountBalance(poolVault)).value.amount);
+    const supply = parseInt((await provider.connection.getTokenSupply(lpMint)).value.amount);
+    const sharesBefore = parseInt((await provider.connection.getTokenAccountBalance(userLpAccount)).value.amount);
+    await addLiquidity(admin.publicKey, user, poolMint, BET_AMOUNT);
+    const sharesAfter = parseInt((await provider.connection.getTokenAccountBalance(userLpAccount)).value.amount);
+    const expected = new anchor.BN(BET_AMOUNT).mul(new anchor.BN(supply + 1000)).div(new anchor.BN(vaultBalance + 1000));
+    expect(sharesAfter - sharesBefore).to.equal(expected.toNumber());
+    await betReturn(admin, executer, betState, await revealSecret(betState));
+  });
+
+  it('Exposure limits Sol', async () => {
+    // a 2x payout of BET_AMOUNT is more than 0.01% of the vault
+    await setRiskLimits(admin, NATIVE_MINT, { ...NO_RISK_LIMITS, maxPayoutPercent: 0.01 });
+    await commitSecret();
+    await expect(bet(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32))).to.be.rejectedWith("Payout Limit Exceeded");
+
+    await setRiskLimits(admin, NATIVE_MINT, { ...NO_RISK_LIMITS, maxUserLiability: 3 * BET_AMOUNT });
+    let betState = await bet(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32));
+    await commitSecret();
+    await expect(bet(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32))).to.be.rejectedWith("User Exposure Exceeded");
+    await betReturn(admin, executer, betState, await revealSecret(betState));
+
+    // room for one more bet in the current epoch-sized window
+    const [mintLedger] = await getMintLedger(program.programId, NATIVE_MINT, admin.publicKey);
+    const { windowVolume } = await program.account.mintLedger.fetch(mintLedger);
+    await setRiskLimits(admin, NATIVE_MINT, {
+      ...NO_RISK_LIMITS,
+      maxWindowVolume: windowVolume.toNumber() + BET_AMOUNT,
+      volumeWindowSlots: 1_000_000_000
+    });
+    await updateCoreState(admin, { randomnessMode: RandomnessMode.SlotHash });
+    await betDirectly(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT, true);
+    await expect(betDirectly(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT, true)).to.be.rejectedWith("Volume Limit Exceeded");
+
+    await updateCoreState(admin);
+    await setRiskLimits(admin, NATIVE_MINT, NO_RISK_LIMITS);
+  });
+
+  it('Update and deregister Spl', async () => {
+    const oldMint = await createMint(
+      provider.connection,
+      admin,
+      tokenMintAuthority.publicKey,
+      tokenMintAuthority.publicKey,
+      9
+    );
+    await register(admin, oldMint, [BET_AMOUNT]);
+
+    // more amounts than the account was sized for
+    const newAmounts = Array.from({ length: 20 }, (_, i) => (i + 1) * BET_AMOUNT);
+    await updateAllowedBets(admin, oldMint, newAmounts);
+    const [allowed] = await getAllowed(program.programId, oldMint, admin.publicKey);
+    const { amounts: storedAmounts } = await program.account.allowedBets.fetch(allowed);
+    expect(storedAmounts.map(i => i.toNumber())).to.deep.equal(newAmounts);
+
+    const adminOldMintAccount = await createAssociatedTokenAccount(
+      provider.connection,
+      admin,
+      oldMint,
+      admin.publicKey
+    );
+    await mintTo(
+      provider.connection,
+      admin,
+      oldMint,
+      adminOldMintAccount,
+      tokenMintAuthority,
+      DEPOSIT_AMOUNT
+    );
+    await deposit(admin, oldMint, DEPOSIT_AMOUNT);
+
+    await setMintEnabled(admin, oldMint, false);
+    await commitSecret();
+    await expect(bet(admin.publicKey, user, oldMint, BET_AMOUNT, true, randomBytes(32))).to.be.rejectedWith("Mint Disabled");
+
+    await deregisterMint(admin, oldMint);
+    const balance = parseInt((await provider.connection.getTokenAccountBalance(adminOldMintAccount)).value.amount);
+    expect(balance).to.equal(DEPOSIT_AMOUNT);
+    expect(await provider.connection.getAccountInfo(allowed)).to.be.null;
+  });
+
+  it('Bet Sol with ranged amounts', async () => {
+    await expect(updateAllowedBets(admin, NATIVE_MINT, [], { min: 2 * BET_AMOUNT, max: BET_AMOUNT, step: BET_AMOUNT })).to.be.rejectedWith("Invalid Amount Range");
+    await updateAllowedBets(admin, NATIVE_MINT, [], { min: BET_AMOUNT, max: 10 * BET_AMOUNT, step: BET_AMOUNT });
+
+    await commitSecret();
+    await expect(bet(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT + 1, true, randomBytes(32))).to.be.rejectedWith("Amount not allowed");
+    await expect(bet(admin.publicKey, user, NATIVE_MINT, 11 * BET_AMOUNT, true, randomBytes(32))).to.be.rejectedWith("Amount not allowed");
+    let betState = await bet(admin.publicKey, user, NATIVE_MINT, 2 * BET_AMOUNT, true, randomBytes(32));
+    await betReturn(admin, executer, betState, await revealSecret(betState));
+
+    await updateAllowedBets(admin, NATIVE_MINT, amounts);
+  });
+
+  it('Transfer admin', async () => {
+    const house = admin.publicKey;
+    const newAdmin = Keypair.generate();
+
+    await proposeAdmin(admin, house, newAdmin.publicKey);
+    await expect(acceptAdmin(user, house)).to.be.rejectedWith("Not Pending Admin");
+    await acceptAdmin(newAdmin, house);
+
+    // PDAs keep their house_id seeds while the admin changes
+    let { admin: currentAdmin } = await program.account.coreState.fetch(coreStateAddress);
+    expect(currentAdmin.toBase58()).to.equal(newAdmin.publicKey.toBase58());
+    await expect(updateCoreState(admin)).to.be.rejectedWith("Wrong Admin Address");
+
+    await proposeAdmin(newAdmin, house, admin.publicKey);
+    await acceptAdmin(admin, house);
+    ({ admin: currentAdmin } = await program.account.coreState.fetch(coreStateAddress));
+    expect(currentAdmin.toBase58()).to.equal(admin.publicKey.toBase58());
+  });
+
+  it('Rotate executers', async () => {
+    await addExecuter(admin, false_executer.publicKey);
+    await expect(addExecuter(admin, false_executer.publicKey)).to.be.rejectedWith("Executer Already Added");
+
+    await commitSecret();
+    let betState = await bet(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32));
+    await betReturn(admin, false_executer, betState, await revealSecret(betState));
+
+    await removeExecuter(admin, false_executer.publicKey);
+    await commitSecret();
+    betState = await bet(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32));
+    await expect(betReturn(admin, false_executer, betState, await revealSecret(betState))).to.be.rejectedWith("Wrong Executer");
+    await betReturn(admin, executer, betState, await revealSecret(betState));
+  });
+
+  it('Bet Sol events', async () => {
+    const events = [];
+    const listeners = ['BetPlaced', 'BetSettled'].map(name =>
+      program.addEventListener(name, (event, slot) => events.push({ name, event }))
+    );
+
+    await commitSecret();
+    const betState = await bet(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32));
+    const { commitment } = await program.account.betState.fetch(betState);
+    const secret = await revealSecret(betState);
+    await betReturn(admin, executer, betState, secret);
+    await new Promise(resolve => setTimeout(resolve, 1000));
+    for (const listener of listeners) {
+      await program.removeEventListener(listener);
+    }
+
+    const placed = events.find(e => e.name === 'BetPlaced' && e.event.betState.equals(betState));
+    const settled = events.find(e => e.name === 'BetSettled' && e.event.betState.equals(betState));
+    expect(placed.event.user.toBase58()).to.equal(user.publicKey.toBase58());
+    expect(placed.event.tokenMint.toBase58()).to.equal(NATIVE_MINT.toBase58());
+    expect(settled.event.flipCounter.toNumber()).to.equal(placed.event.flipCounter.toNumber());
+    expect(settled.event.paidOut.toNumber()).to.equal(settled.event.isWin ? placed.event.payout.toNumber() : 0);
+
+    // the commitment and the entropy can be checked from the event alone
+    const eventSecret = Buffer.from(settled.event.secret);
+    const eventSlotHash = Buffer.from(settled.event.slotHash);
+    expect(eventSecret.equals(secret)).to.be.true;
+    expect(createHash('sha256').update(eventSecret).digest().equals(Buffer.from(commitment))).to.be.true;
+    const entropy = createHash('sha256').update(eventSecret).update(eventSlotHash).digest();
+    expect(entropy.equals(Buffer.from(settled.event.entropy))).to.be.true;
+    expect(settled.event.signature).to.be.null;
+  });
+
+  it('Split admin roles', async () => {
+    const house = admin.publicKey;
+    const defaultRoles = {
+      pauser: admin.publicKey,
+      treasurer: admin.publicKey,
+      riskManager: admin.publicKey,
+      withdrawDestinations: [admin.publicKey],
+    };
+
+    await setRoles(admin, { ...defaultRoles, pauser: user.publicKey });
+    await expect(setActive(admin, house, false)).to.be.rejectedWith("Wrong Pauser");
+    await setActive(user, house, false);
+    await expect(bet(house, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32))).to.be.rejectedWith("Not Active");
+    await setActive(user, house, true);
+
+    await expect(withdraw(admin, NATIVE_MINT, WITHDRAW_AMOUNT, admin, user.publicKey)).to.be.rejectedWith("Destination Not Whitelisted");
+    await expect(withdraw(admin, NATIVE_MINT, WITHDRAW_AMOUNT, user)).to.be.rejectedWith("Wrong Treasurer");
+
+    await setRoles(admin, { ...defaultRoles, withdrawDestinations: [admin.publicKey, user.publicKey] });
+    await withdraw(admin, NATIVE_MINT, WITHDRAW_AMOUNT, admin, user.publicKey);
+
+    await setRoles(admin, defaultRoles);
+  });
+
+  it('Claim fees Sol', async () => {
+    const [mintLedger] = await getMintLedger(program.programId, NATIVE_MINT, admin.publicKey);
+    const [feeVault] = await getNativeFeeVault(program.programId, admin.publicKey);
+    const rentReserve = await provider.connection.getMinimumBalanceForRentExemption(0);
+    const unclaimedFees = async () => {
+      const { totalFees, claimedFees } = await program.account.mintLedger.fetch(mintLedger);
+      return totalFees.toNumber() - claimedFees.toNumber();
+    };
+
+    const feeVaultBefore = await provider.connection.getBalance(feeVault);
+    await commitSecret();
+    const betState = await bet(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32));
+    const { fee } = await program.account.betState.fetch(betState);
+    await betReturn(admin, executer, betState, await revealSecret(betState));
+    expect(await provider.connection.getBalance(feeVault) - feeVaultBefore).to.equal(fee.toNumber());
+    expect(await unclaimedFees()).to.equal(await provider.connection.getBalance(feeVault) - rentReserve);
+
+    const fees = await unclaimedFees();
+    await expect(claimFees(admin, NATIVE_MINT, fees + 1)).to.be.rejectedWith("Insufficient Fees");
+    await expect(claimFees(admin, NATIVE_MINT, fees, admin, user.publicKey)).to.be.rejectedWith("Destination Not Whitelisted");
+    await claimFees(admin, NATIVE_MINT, fees);
+    expect(await unclaimedFees()).to.equal(0);
+    expect(await provider.connection.getBalance(feeVault)).to.equal(rentReserve);
+
+    // the fee of a pending bet cannot be claimed, so the bet can still be refunded
+    await placeRefundableBets();
+    const pendingBet = await bet(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32));
+    const { fee: pendingFee } = await program.account.betState.fetch(pendingBet);
+    expect((await program.account.mintLedger.fetch(mintLedger)).pendingFees.toNumber()).to.equal(pendingFee.toNumber());
+    await expect(claimFees(admin, NATIVE_MINT, 1)).to.be.rejectedWith("Insufficient Fees");
+
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    await refundExpiredBet(admin.publicKey, user, pendingBet);
+    await updateCoreState(admin);
+    expect((await program.account.mintLedger.fetch(mintLedger)).pendingFees.toNumber()).to.equal(0);
+    expect(await provider.connection.getBalance(feeVault)).to.equal(rentReserve);
+  });
+
+  it('Referral Sol', async () => {
+    const house = admin.publicKey;
+    const referrer = false_executer;
+    await updateCoreState(admin, { referralSharePercent: 20 });
+
+    await expect(registerReferral(house, user, user.publicKey)).to.be.rejectedWith("Invalid Referrer");
+    await registerReferral(house, user, referrer.publicKey);
+    // the link is one-time
+    await expect(registerReferral(house, user, admin.publicKey)).to.be.rejected;
+    const referrerAccount = await initReferrer(house, referrer, NATIVE_MINT);
+
+    await commitSecret();
+    await expect(bet(house, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32), admin.publicKey)).to.be.rejectedWith("Referrer Mismatch");
+    const betState = await bet(house, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32), referrer.publicKey);
+    const { fee } = await program.account.betState.fetch(betState);
+    // the reward is credited once the bet settles
+    expect((await program.account.referrer.fetch(referrerAccount)).accrued.toNumber()).to.equal(0);
+    await betReturn(admin, executer, betState, await revealSecret(betState));
+
+    const { accrued } = await program.account.referrer.fetch(referrerAccount);
+    expect(accrued.toNumber()).to.equal(Math.floor(fee.toNumber() * 20 / 100));
+
+    const balanceBefore = await provider.connection.getBalance(referrer.publicKey);
+    await claimReferralRewards(house, referrer, NATIVE_MINT);
+    const balanceAfter = await provider.connection.getBalance(referrer.publicKey);
+    // the referrer also pays the transaction fee
+    expect(balanceAfter).to.be.greaterThan(balanceBefore + accrued.toNumber() - 10000);
+    await expect(claimReferralRewards(house, referrer, NATIVE_MINT)).to.be.rejectedWith("Nothing To Claim");
+
+    // a refunded bet never pays its referrer
+    await placeRefundableBets({ referralSharePercent: 20 });
+    const refundedBet = await bet(house, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32), referrer.publicKey);
+    expect((await program.account.betState.fetch(refundedBet)).referralReward.toNumber()).to.be.greaterThan(0);
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    await refundExpiredBet(house, user, refundedBet);
+    expect((await program.account.referrer.fetch(referrerAccount)).accrued.toNumber()).to.equal(0);
+
+    await updateCoreState(admin);
+  });
+
+  it('Player stats Sol', async () => {
+    const [playerStats] = await getPlayerStats(program.programId, user.publicKey, admin.publicKey);
+    const before = await program.account.playerStats.fetch(playerStats);
+    const wageredBefore = before.mints.find(m => m.tokenMint.equals(NATIVE_MINT)).totalWagered.toNumber();
+
+    await commitSecret();
+    const betState = await bet(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32));
+    const { placedSlot } = await program.account.betState.fetch(betState);
+    await betReturn(admin, executer, betState, await revealSecret(betState));
+
+    const after = await program.account.playerStats.fetch(playerStats);
+    expect(after.totalBets.toNumber()).to.equal(before.totalBets.toNumber() + 1);
+    expect(after.wins.toNumber() + after.losses.toNumber()).to.equal(after.totalBets.toNumber());
+    expect(after.lastBetSlot.toNumber()).to.equal(placedSlot.toNumber());
+    const won = after.wins.toNumber() > before.wins.toNumber();
+    expect(after.currentStreak.toNumber() > 0).to.equal(won);
+    expect(after.longestWinStreak.toNumber()).to.be.at.least(won ? after.currentStreak.toNumber() : 0);
+    const wageredAfter = after.mints.find(m => m.tokenMint.equals(NATIVE_MINT)).totalWagered.toNumber();
+    expect(wageredAfter - wageredBefore).to.equal(BET_AMOUNT);
+  });
+
+  it('Loyalty points Sol', async () => {
+    const house = admin.publicKey;
+    const POINTS_PER_FREE_BET = 1000;
+    const [playerStats] = await getPlayerStats(program.programId, user.publicKey, house);
+    await expect(setLoyaltyMint(admin, 101, POINTS_PER_FREE_BET)).to.be.rejectedWith("Invalid Loyalty Rate");
+    const loyaltyMint = await setLoyaltyMint(admin, 100, POINTS_PER_FREE_BET);
+    const { mintAuthority, decimals } = await getMint(provider.connection, loyaltyMint);
+    expect(mintAuthority.toBase58()).to.equal(vaultAuth.toBase58());
+    expect(decimals).to.equal(0);
+
+    const { loyaltyPoints: pointsBefore } = await program.account.playerStats.fetch(playerStats);
+    await commitSecret();
+    let betState = await bet(house, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32));
+    const { fee } = await program.account.betState.fetch(betState);
+    // points are credited once the bet settles
+    expect((await program.account.playerStats.fetch(playerStats)).loyaltyPoints.toNumber()).to.equal(pointsBefore.toNumber());
+    await betReturn(admin, executer, betState, await revealSecret(betState));
+    const { loyaltyPoints } = await program.account.playerStats.fetch(playerStats);
+    expect(loyaltyPoints.toNumber() - pointsBefore.toNumber()).to.equal(fee.toNumber());
+
+    const userLoyaltyAccount = await claimLoyaltyPoints(house, user);
+    const balance = await provider.connection.getTokenAccountBalance(userLoyaltyAccount);
+    expect(Number(balance.value.amount)).to.equal(loyaltyPoints.toNumber());
+    expect((await program.account.playerStats.fetch(playerStats)).loyaltyPoints.toNumber()).to.equal(0);
+    await expect(claimLoyaltyPoints(house, user)).to.be.rejectedWith("Nothing To Claim");
+
+    // a refunded bet earns no points, and gives back the free bet it used
+    await placeRefundableBets();
+    const paidBet = await bet(house, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32));
+    await redeemLoyaltyPoints(house, user, 1);
+    const freeBet = await bet(house, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32));
+    expect((await program.account.playerStats.fetch(playerStats)).freeFeeBets.toNumber()).to.equal(0);
+    await new Promise(resolve => setTimeout(resolve, 2000));
+    await refundExpiredBet(house, user, paidBet);
+    await refundExpiredBet(house, user, freeBet);
+    await updateCoreState(admin);
+    const { freeFeeBets, loyaltyPoints: pointsAfterRefund } = await program.account.playerStats.fetch(playerStats);
+    expect(freeFeeBets.toNumber()).to.equal(1);
+    expect(pointsAfterRefund.toNumber()).to.equal(0);
+
+    await commitSecret();
+    betState = await bet(house, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32));
+    expect((await program.account.betState.fetch(betState)).fee.toNumber()).to.equal(0);
+    await betReturn(admin, executer, betState, await revealSecret(betState));
+    expect((await program.account.playerStats.fetch(playerStats)).freeFeeBets.toNumber()).to.equal(0);
+  });
+
+  it('Timelocked config Sol', async () => {
+    const NEW_WIN_RATIO = 40;
+    const NEW_FEE_PERCENT = 4;
+    await expect(proposeConfig(admin, 60, FEE_PERCENT, MIN_CONFIG_DELAY)).to.be.rejectedWith("RTP Ceiling Exceeded");
+    await expect(proposeConfig(admin, 101, FEE_PERCENT, MIN_CONFIG_DELAY)).to.be.rejectedWith("Invalid Win Ratio");
+    await expect(proposeConfig(admin, NEW_WIN_RATIO, 101, MIN_CONFIG_DELAY)).to.be.rejectedWith("Invalid Fee Percent");
+    await expect(proposeConfig(admin, NEW_WIN_RATIO, FEE_PERCENT, 0)).to.be.rejectedWith("Invalid Config Delay");
+    await proposeConfig(admin, NEW_WIN_RATIO, NEW_FEE_PERCENT, MIN_CONFIG_DELAY);
+    await expect(applyConfig(admin.publicKey)).to.be.rejectedWith("Config Not Ready");
+    const [pendingConfig] = await getPendingConfig(program.programId, admin.publicKey);
+    const { effectiveAt } = await program.account.pendingConfig.fetch(pendingConfig);
+    const { configDelay } = await program.account.coreState.fetch(coreStateAddress);
+    expect(configDelay.toNumber()).to.equal(MIN_CONFIG_DELAY);
+    expect(effectiveAt.toNumber()).to.be.at.least(Math.floor(Date.now() / 1000) + MIN_CONFIG_DELAY - 60);
+
+    // bets keep being placed under the current win_ratio until it applies
+    await commitSecret();
+    const betState = await bet(admin.publicKey, user, NATIVE_MINT, BET_AMOUNT, true, randomBytes(32));
+    expect((await program.account.betState.fetch(betState)).winRatio).to.equal(WIN_RATIO * 100);
+
+    await new Promise(resolve => setTimeout(resolve, (MIN_CONFIG_DELAY + 1) * 1000));
+    await applyConfig(admin.publicKey);
+    const { winRatio, feePercent } = await program.account.coreState.fetch(coreStateAddress);
+    expect(winRatio).to.equal(NEW_WIN_RATIO * 100);
+    expect(feePercent).to.equal(NEW_FEE_PERCENT * 100);
+    expect(await provider.connection.getAccountInfo(pendingConfig)).to.be.null;
+    // the pending bet keeps the odds it was placed under
+    expect((await program.account.betState.fetch(betState)).winRatio).to.equal(WIN_RATIO * 100);
+    await betReturn(admin, executer, betState, await revealSecret(betState));
+
+    await proposeConfig(admin, WIN_RATIO, FEE_PERCENT, MIN_CONFIG_DELAY);
+    await new Promise(resolve => setTimeout(resolve, (MIN_CONFIG_DELAY + 1) * 1000));
+    await applyConfig(admin.publicKey);
+  });
+
+  it('Migrate legacy vaults', async () => {
+    for (const wallet of [LEGACY_ADMIN, LEGACY_USER]) {
+      await program.provider.connection.confirmTransaction(
+        await program.provider.connection.requestAirdrop(wallet.publicKey, AIRDROP_AMOUNT),
+        "confirmed"
+      );
+    }
+    // a new house for the legacy admin, which cannot reuse the admin key as house_id
+    const house = Keypair.generate().publicKey;
+    const { vaultAuthority } = await initialize(LEGACY_ADMIN, executer, FEE_PERCENT, WIN_RATIO, RandomnessMode.CommitReveal, PublicKey.default, 2, 100, 2, MIN_CONFIG_DELAY, house);
+    await register(LEGACY_ADMIN, NATIVE_MINT, amounts, undefined, null, house);
+    await register(LEGACY_ADMIN, LEGACY_MINT, amounts, undefined, null, house);
+
+    // SOL
+    const [legacyVaultAuthority] = await getVaultAuth(program.programId, LEGACY_ADMIN.publicKey);
+    const legacyReserve = await provider.connection.getMinimumBalanceForRentExemption(0);
+    const legacySol = await provider.connection.getBalance(legacyVaultAuthority) - legacyReserve;
+    await expect(refundLegacyBet(house, LEGACY_USER, NATIVE_MINT, LEGACY_BET_STATE_SOL)).to.be.rejectedWith("Invalid Legacy State");
+    const vaultBefore = await provider.connection.getBalance(vaultAuthority);
+    await migrateVault(LEGACY_ADMIN, house, NATIVE_MINT);
+    expect(await provider.connection.getBalance(vaultAuthority) - vaultBefore).to.equal(legacySol);
+    expect(await provider.connection.getBalance(legacyVaultAuthority)).to.equal(legacyReserve);
+
+    // the bet still pending in the legacy vault is refunded from the new one
+    const balanceBefore = await provider.connection.getBalance(LEGACY_USER.publicKey);
+    await expect(refundLegacyBet(house, user, NATIVE_MINT, LEGACY_BET_STATE_SOL)).to.be.rejectedWith("Invalid Legacy State");
+    await refundLegacyBet(house, LEGACY_USER, NATIVE_MINT, LEGACY_BET_STATE_SOL);
+    // the legacy bet_state rent comes back too, less the transaction fee
+    expect(await provider.connection.getBalance(LEGACY_USER.publicKey)).to.be.greaterThan(balanceBefore + LEGACY_BET_AMOUNT_SOL);
+    expect(await provider.connection.getBalance(vaultAuthority)).to.equal(vaultBefore + legacySol - LEGACY_BET_AMOUNT_SOL);
+    expect(await provider.connection.getAccountInfo(LEGACY_BET_STATE_SOL)).to.be.null;
+    await expect(refundLegacyBet(house, LEGACY_USER, NATIVE_MINT, LEGACY_BET_STATE_SOL)).to.be.rejected;
+
+    // SPL
+    const [legacyVaultTokenAccount] = await getVaultTokenAccount(program.programId, LEGACY_MINT, LEGACY_ADMIN.publicKey);
+    const [vaultTokenAccount] = await getVaultTokenAccount(program.programId, LEGACY_MINT, house);
+    const tokenBalance = async (account) => parseInt((await provider.connection.getTokenAccountBalance(account)).value.amount);
+    const legacyTokens = await tokenBalance(legacyVaultTokenAccount);
+    await migrateVault(LEGACY_ADMIN, house, LEGACY_MINT);
+    expect(await tokenBalance(vaultTokenAccount)).to.equal(legacyTokens);
+    expect(await tokenBalance(legacyVaultTokenAccount)).to.equal(0);
+
+    const legacyUserTokenAccount = await createAssociatedTokenAccount(provider.connection, LEGACY_USER, LEGACY_MINT, LEGACY_USER.publicKey);
+    // a legacy bet is only refunded in its own mint
+    await expect(refundLegacyBet(house, LEGACY_USER, NATIVE_MINT, LEGACY_BET_STATE_SPL)).to.be.rejectedWith("Invalid Legacy State");
+    await refundLegacyBet(house, LEGACY_USER, LEGACY_MINT, LEGACY_BET_STATE_SPL);
+    expect(await tokenBalance(legacyUserTokenAccount)).to.equal(LEGACY_BET_AMOUNT_SPL);
+    expect(await tokenBalance(vaultTokenAccount)).to.equal(legacyTokens - LEGACY_BET_AMOUNT_SPL);
+    expect(await provider.connection.getAccountInfo(LEGACY_BET_STATE_SPL)).to.be.null;
+  });
+
   it('Update CoreState', async () => {
-    const NEW_FEE_PERCENT = 2;
-    const coreState = await updateCoreState(admin, NEW_FEE_PERCENT, false, false);
+    const coreState = await updateCoreState(admin, { active: false, allowDirectBet: false });
 
     console.log("Core State: ", coreState.toBase58(), await program.account.coreState.fetch(coreState));
   });
@@ -0,0 +1,26 @@
+import { Keypair, PublicKey } from '@solana/web3.js';
+
+// Accounts from before house_id seeds, loaded into the test validator from
+// tests/fixtures (see Anchor.toml). The legacy admin has a CoreState with two pending
+// bets placed by the legacy user, one in SOL and one in LEGACY_MINT, and a vault
+// holding three times each bet's amount.
+
+export const LEGACY_ADMIN = Keypair.fromSecretKey(Uint8Array.from([
+  107, 34, 85, 107, 82, 133, 219, 23, 230, 240, 50, 133, 106, 92, 104, 164,
+  61, 237, 56, 110, 98, 115, 234, 94, 78, 102, 85, 113, 31, 127, 245, 193,
+  153, 19, 237, 14, 157, 65, 249, 249, 42, 115, 253, 9, 166, 90, 250, 73,
+  31, 160, 241, 43, 228, 7, 253, 185, 101, 22, 195, 19, 98, 84, 6, 158
+]));
+
+export const LEGACY_USER = Keypair.fromSecretKey(Uint8Array.from([
+  165, 92, 219, 248, 206, 186, 168, 47, 231, 58, 183, 201, 252, 10, 128, 88,
+  95, 23, 188, 50, 57, 163, 186, 99, 147, 147, 203, 253, 170, 0, 73, 9,
+  65, 24, 176, 189, 167, 220, 53, 200, 118, 72, 37, 88, 173, 235, 5, 194,
+  198, 163, 178, 165, 197, 207, 125, 235, 30, 79, 150, 224, 6, 220, 38, 133
+]));
+
+export const LEGACY_MINT = new PublicKey("HFHAgJwuCpTkfN5BvwfxTtMBvzczxq4u6uyZZpTMmzXT");
+export const LEGACY_BET_STATE_SOL = new PublicKey("989y19pEVaLaGgkzhq9VdbdiJcqWjwQMja6UxLFUy1np");
+export const LEGACY_BET_STATE_SPL = new PublicKey("B8L733WbA6rAd17RuHAmDSHpgMdfH7YmRFArHZxp5yLB");
+export const LEGACY_BET_AMOUNT_SOL = 100_000_000;
+export const LEGACY_BET_AMOUNT_SPL = 1_000_000;
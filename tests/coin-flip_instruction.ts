@@ -1,127 +1,799 @@
 import * as anchor from '@project-serum/anchor';
 import { Program } from '@project-serum/anchor';
-import { Keypair, PublicKey, SystemProgram, SYSVAR_RENT_PUBKEY, Transaction } from '@solana/web3.js';
-import { getAssociatedTokenAddress, TOKEN_PROGRAM_ID, NATIVE_MINT } from '@solana/spl-token';
+import { Ed25519Program, Keypair, PublicKey, SystemProgram, SYSVAR_INSTRUCTIONS_PUBKEY, SYSVAR_RENT_PUBKEY, SYSVAR_SLOT_HASHES_PUBKEY, Transaction, TransactionInstruction } from '@solana/web3.js';
+import { getAssociatedTokenAddress, ASSOCIATED_TOKEN_PROGRAM_ID, TOKEN_PROGRAM_ID, NATIVE_MINT } from '@solana/spl-token';
 import { CoinFlip } from '../target/types/coin_flip';
 import {
   getCoreState,
   getVaultAuth,
   getVaultTokenAccount,
-  getBetState, getAllowed
+  getBetState, getAllowed,
+  getCommitmentQueue,
+  getMintLedger,
+  getRentSponsor,
+  getLpMint,
+  getUserExposure,
+  getPendingConfig,
+  getFeeVaultTokenAccount,
+  getNativeFeeVault,
+  getReferralLink,
+  getReferrer,
+  getPlayerStats,
+  getLoyaltyMint
 } from './coin-flip_pda';
 
 const program = anchor.workspace.CoinFlip as Program<CoinFlip>;
 
-export async function initialize(admin: Keypair, executer: Keypair, feePercent: number, winRatio: number) {
-  const [coreState, coreStateNonce] = await getCoreState(program.programId, admin.publicKey);
-  const [vaultAuthority, vaultAuthNonce] = await getVaultAuth(program.programId, admin.publicKey);
+// MIN_CONFIG_DELAY in the program built with the short-config-delay feature, seconds
+export const MIN_CONFIG_DELAY = 5;
+
+export const RandomnessMode = {
+  LegacyHash: { legacyHash: {} },
+  SlotHash: { slotHash: {} },
+  CommitReveal: { commitReveal: {} },
+  Ed25519Oracle: { ed25519Oracle: {} },
+};
+
+// The tests use the admin key as house_id, so the helpers below derive PDAs from the
+// admin key until the admin is rotated.
+// SPL fees go to a fee vault token account per mint, native fees to one PDA per house
+async function getFeeVault(tokenMint: PublicKey, house: PublicKey) {
+  const [feeVault] = (tokenMint.toBase58() === NATIVE_MINT.toBase58()) ?
+    await getNativeFeeVault(program.programId, house) :
+    await getFeeVaultTokenAccount(program.programId, tokenMint, house);
+  return feeVault;
+}
+
+// only CommitReveal houses need a commitment queue, the others pass the user instead
+async function getBetCommitmentQueue(house: PublicKey, user: PublicKey) {
+  const [coreState] = await getCoreState(program.programId, house);
+  const { randomnessMode } = await program.account.coreState.fetch(coreState);
+  if (!('commitReveal' in randomnessMode)) {
+    return user;
+  }
+  const [commitmentQueue] = await getCommitmentQueue(program.programId, house);
+  return commitmentQueue;
+}
+
+// a bet with a referrer passes the user's ReferralLink and the referrer's account
+async function referralAccounts(house: PublicKey, user: PublicKey, tokenMint: PublicKey, referrer: PublicKey | null) {
+  if (referrer === null) {
+    return [];
+  }
+  const [referralLink] = await getReferralLink(program.programId, user, house);
+  const [referrerAccount] = await getReferrer(program.programId, tokenMint, referrer, house);
+  return [
+    { pubkey: referralLink, isWritable: false, isSigner: false },
+    { pubkey: referrerAccount, isWritable: true, isSigner: false },
+  ];
+}
+
+// the referrer's account is credited when the bet settles
+async function settlementReferralAccounts(house: PublicKey, betState: PublicKey) {
+  const { tokenMint, referrer, referralReward } = await program.account.betState.fetch(betState);
+  if (referralReward.isZero()) {
+    return [];
+  }
+  const [referrerAccount] = await getReferrer(program.programId, tokenMint, referrer, house);
+  return [
+    { pubkey: referrerAccount, isWritable: true, isSigner: false },
+  ];
+}
+
+export async function initialize(admin: Keypair, executer: Keypair, feePercent: number, winRatio: number, randomnessMode = RandomnessMode.CommitReveal, oracle: PublicKey = PublicKey.default, payoutMultiplier = 2, maxRtpPercent = 100, diceEdgePercent = 2, configDelay = MIN_CONFIG_DELAY, houseId = admin.publicKey) {
+  const [coreState, coreStateNonce] = await getCoreState(program.programId, houseId);
+  const [vaultAuthority, vaultAuthNonce] = await getVaultAuth(program.programId, houseId);
   await program.rpc.initialize({
+    houseId,
     coreStateNonce,
     vaultAuthNonce,
     feePercent: new anchor.BN(feePercent * 100),
-    winRatio: new anchor.BN(winRatio * 100)
+    winRatio: new anchor.BN(winRatio * 100),
+    oracle,
+    randomnessMode,
+    payoutMultiplierBps: Math.round(payoutMultiplier * 10000),
+    maxRtpBps: maxRtpPercent * 100,
+    diceEdgeBps: diceEdgePercent * 100,
+    configDelay: new anchor.BN(configDelay)
+  }, {
+    accounts: {
+      admin: admin.publicKey,
+      executer: executer.publicKey,
+      coreState,
+      vaultAuthority,
+      systemProgram: SystemProgram.programId
+    },
+    signers: [admin]
+  });
+  return { coreState, vaultAuthority };
+}
+
+export async function addExecuter(admin: Keypair, executer: PublicKey) {
+  const [coreState] = await getCoreState(program.programId, admin.publicKey);
+  await program.rpc.addExecuter({
+    executer
+  }, {
+    accounts: {
+      admin: admin.publicKey,
+      coreState,
+    },
+    signers: [admin]
+  });
+}
+
+export async function removeExecuter(admin: Keypair, executer: PublicKey) {
+  const [coreState] = await getCoreState(program.programId, admin.publicKey);
+  await program.rpc.removeExecuter({
+    executer
+  }, {
+    accounts: {
+      admin: admin.publicKey,
+      coreState,
+    },
+    signers: [admin]
+  });
+}
+
+export async function proposeAdmin(admin: Keypair, house: PublicKey, newAdmin: PublicKey) {
+  const [coreState] = await getCoreState(program.programId, house);
+  await program.rpc.proposeAdmin({
+    newAdmin
+  }, {
+    accounts: {
+      admin: admin.publicKey,
+      coreState,
+    },
+    signers: [admin]
+  });
+}
+
+export async function acceptAdmin(newAdmin: Keypair, house: PublicKey) {
+  const [coreState] = await getCoreState(program.programId, house);
+  await program.rpc.acceptAdmin({
+    accounts: {
+      newAdmin: newAdmin.publicKey,
+      coreState,
+    },
+    signers: [newAdmin]
+  });
+}
+
+export type Roles = {
+  pauser: PublicKey,
+  treasurer: PublicKey,
+  riskManager: PublicKey,
+  withdrawDestinations: PublicKey[],
+};
+
+export async function proposeConfig(admin: Keypair, winRatio: number, feePercent: number, configDelay: number) {
+  const [coreState] = await getCoreState(program.programId, admin.publicKey);
+  const [pendingConfig] = await getPendingConfig(program.programId, admin.publicKey);
+  await program.rpc.proposeConfig({
+    winRatio: Math.round(winRatio * 100),
+    feePercent: Math.round(feePercent * 100),
+    configDelay: new anchor.BN(configDelay)
+  }, {
+    accounts: {
+      admin: admin.publicKey,
+      coreState,
+      pendingConfig,
+      systemProgram: SystemProgram.programId
+    },
+    signers: [admin]
+  });
+  return pendingConfig;
+}
+
+export async function applyConfig(admin: PublicKey) {
+  const [coreState] = await getCoreState(program.programId, admin);
+  const [pendingConfig] = await getPendingConfig(program.programId, admin);
+  await program.rpc.applyConfig({
+    accounts: {
+      coreState,
+      pendingConfig,
+      admin
+    }
+  });
+}
+
+export async function setRoles(admin: Keypair, roles: Roles) {
+  const [coreState] = await getCoreState(program.programId, admin.publicKey);
+  await program.rpc.setRoles(roles, {
+    accounts: {
+      admin: admin.publicKey,
+      coreState,
+    },
+    signers: [admin]
+  });
+}
+
+export async function setActive(pauser: Keypair, house: PublicKey, active: boolean) {
+  const [coreState] = await getCoreState(program.programId, house);
+  await program.rpc.setActive({
+    active
+  }, {
+    accounts: {
+      pauser: pauser.publicKey,
+      coreState,
+    },
+    signers: [pauser]
+  });
+}
+
+export type RiskLimits = {
+  maxPayoutPercent: number,
+  maxUserLiability: number,
+  maxWindowVolume: number,
+  volumeWindowSlots: number,
+};
+
+// zero limits are not enforced
+export const NO_RISK_LIMITS: RiskLimits = {
+  maxPayoutPercent: 0,
+  maxUserLiability: 0,
+  maxWindowVolume: 0,
+  volumeWindowSlots: 1,
+};
+
+export type AmountRange = {
+  min: number,
+  max: number,
+  step: number,
+};
+
+function toAmountRange(amountRange: AmountRange | null) {
+  return amountRange ? {
+    min: new anchor.BN(amountRange.min),
+    max: new anchor.BN(amountRange.max),
+    step: new anchor.BN(amountRange.step)
+  } : null;
+}
+
+export async function register(admin: Keypair, tokenMint: PublicKey, amounts: number[], risk: RiskLimits = NO_RISK_LIMITS, amountRange: AmountRange | null = null, house = admin.publicKey) {
+  const [coreState] = await getCoreState(program.programId, house);
+  const [vaultAuthority] = await getVaultAuth(program.programId, house);
+  const [vaultTokenAccount, vaultTokenAccountNonce] = await getVaultTokenAccount(program.programId, tokenMint, house);
+  const [allowed] = await getAllowed(program.programId, tokenMint, house);
+  const [mintLedger] = await getMintLedger(program.programId, tokenMint, house);
+  const [feeVaultTokenAccount] = await getFeeVaultTokenAccount(program.programId, tokenMint, house);
+  const [nativeFeeVault] = await getNativeFeeVault(program.programId, house);
+  await program.rpc.register({
+    vaultTokenAccountNonce,
+    amounts: amounts.map(i => new anchor.BN(i)),
+    amountRange: toAmountRange(amountRange),
+    maxPayoutBps: risk.maxPayoutPercent * 100,
+    maxUserLiability: new anchor.BN(risk.maxUserLiability),
+    maxWindowVolume: new anchor.BN(risk.maxWindowVolume),
+    volumeWindowSlots: new anchor.BN(risk.volumeWindowSlots)
+  }, {
+    accounts: {
+      allowedBets:
+      allowed,
+      mintLedger,
+      coreState,
+      admin: admin.publicKey,
+      tokenMint,
+      vaultTokenAccount,
+      feeVaultTokenAccount,
+      nativeFeeVault,
+      vaultAuthority,
+      systemProgram: SystemProgram.programId,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      rent: SYSVAR_RENT_PUBKEY
+    },
+    signers: [admin]
+  });
+}
+
+// vaults and core states from before house_id seeds are seeded by the admin key
+export async function migrateVault(admin: Keypair, house: PublicKey, tokenMint: PublicKey) {
+  const [coreState] = await getCoreState(program.programId, house);
+  const [vaultAuthority] = await getVaultAuth(program.programId, house);
+  const [mintLedger] = await getMintLedger(program.programId, tokenMint, house);
+  const [legacyCoreState] = await getCoreState(program.programId, admin.publicKey);
+  const [legacyVaultAuthority] = await getVaultAuth(program.programId, admin.publicKey);
+  const [legacyVaultTokenAccount] = await getVaultTokenAccount(program.programId, tokenMint, admin.publicKey);
+  let vaultTokenAccount;
+  if (tokenMint.toBase58() === NATIVE_MINT.toBase58()) {
+    vaultTokenAccount = vaultAuthority;
+  } else {
+    const [_vaultTokenAccount] = await getVaultTokenAccount(program.programId, tokenMint, house);
+    vaultTokenAccount = _vaultTokenAccount;
+  }
+
+  await program.rpc.migrateVault({
+    accounts: {
+      coreState,
+      admin: admin.publicKey,
+      tokenMint,
+      legacyCoreState,
+      legacyVaultAuthority,
+      legacyVaultTokenAccount,
+      vaultTokenAccount,
+      mintLedger,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId
+    },
+    signers: [admin]
+  });
+}
+
+export async function refundLegacyBet(house: PublicKey, user: Keypair, tokenMint: PublicKey, legacyBetState: PublicKey) {
+  const [coreState] = await getCoreState(program.programId, house);
+  const [vaultAuthority] = await getVaultAuth(program.programId, house);
+  const [mintLedger] = await getMintLedger(program.programId, tokenMint, house);
+  const userTokenAccount = (tokenMint.toBase58() === NATIVE_MINT.toBase58()) ?
+    user.publicKey : (await getAssociatedTokenAddress(tokenMint, user.publicKey));
+  let vaultTokenAccount;
+  if (tokenMint.toBase58() === NATIVE_MINT.toBase58()) {
+    vaultTokenAccount = vaultAuthority;
+  } else {
+    const [_vaultTokenAccount] = await getVaultTokenAccount(program.programId, tokenMint, house);
+    vaultTokenAccount = _vaultTokenAccount;
+  }
+
+  await program.rpc.refundLegacyBet({
+    accounts: {
+      user: user.publicKey,
+      coreState,
+      vaultAuthority,
+      tokenMint,
+      userTokenAccount,
+      vaultTokenAccount,
+      legacyBetState,
+      mintLedger,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId
+    },
+    signers: [user]
+  });
+}
+
+export async function updateAllowedBets(admin: Keypair, tokenMint: PublicKey, amounts: number[], amountRange: AmountRange | null = null) {
+  const [coreState] = await getCoreState(program.programId, admin.publicKey);
+  const [allowed] = await getAllowed(program.programId, tokenMint, admin.publicKey);
+  await program.rpc.updateAllowedBets({
+    amounts: amounts.map(i => new anchor.BN(i)),
+    amountRange: toAmountRange(amountRange)
+  }, {
+    accounts: {
+      coreState,
+      riskManager: admin.publicKey,
+      tokenMint,
+      allowedBets: allowed,
+      systemProgram: SystemProgram.programId
+    },
+    signers: [admin]
+  });
+}
+
+export async function setMintEnabled(admin: Keypair, tokenMint: PublicKey, enabled: boolean) {
+  const [coreState] = await getCoreState(program.programId, admin.publicKey);
+  const [allowed] = await getAllowed(program.programId, tokenMint, admin.publicKey);
+  await program.rpc.setMintEnabled({
+    enabled
+  }, {
+    accounts: {
+      coreState,
+      riskManager: admin.publicKey,
+      tokenMint,
+      allowedBets: allowed,
+      systemProgram: SystemProgram.programId
+    },
+    signers: [admin]
+  });
+}
+
+export async function deregisterMint(admin: Keypair, tokenMint: PublicKey) {
+  const [coreState] = await getCoreState(program.programId, admin.publicKey);
+  const [vaultAuthority] = await getVaultAuth(program.programId, admin.publicKey);
+  const [vaultTokenAccount] = await getVaultTokenAccount(program.programId, tokenMint, admin.publicKey);
+  const [allowed] = await getAllowed(program.programId, tokenMint, admin.publicKey);
+  const [mintLedger] = await getMintLedger(program.programId, tokenMint, admin.publicKey);
+  const [feeVaultTokenAccount] = await getFeeVaultTokenAccount(program.programId, tokenMint, admin.publicKey);
+  const [nativeFeeVault] = await getNativeFeeVault(program.programId, admin.publicKey);
+
+  const destinationTokenAccount = (tokenMint.toBase58() === NATIVE_MINT.toBase58()) ?
+    admin.publicKey : (await getAssociatedTokenAddress(tokenMint, admin.publicKey));
+
+  await program.rpc.deregisterMint({
+    accounts: {
+      coreState,
+      admin: admin.publicKey,
+      tokenMint,
+      destination: admin.publicKey,
+      destinationTokenAccount,
+      vaultAuthority,
+      vaultTokenAccount,
+      feeVaultTokenAccount,
+      nativeFeeVault,
+      allowedBets: allowed,
+      mintLedger,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId
+    },
+    signers: [admin]
+  });
+}
+
+export async function setRiskLimits(admin: Keypair, tokenMint: PublicKey, risk: RiskLimits) {
+  const [coreState] = await getCoreState(program.programId, admin.publicKey);
+  const [allowed] = await getAllowed(program.programId, tokenMint, admin.publicKey);
+  await program.rpc.setRiskLimits({
+    maxPayoutBps: risk.maxPayoutPercent * 100,
+    maxUserLiability: new anchor.BN(risk.maxUserLiability),
+    maxWindowVolume: new anchor.BN(risk.maxWindowVolume),
+    volumeWindowSlots: new anchor.BN(risk.volumeWindowSlots)
+  }, {
+    accounts: {
+      coreState,
+      riskManager: admin.publicKey,
+      tokenMint,
+      allowedBets: allowed,
+    },
+    signers: [admin]
+  });
+}
+
+export async function initCommitmentQueue(admin: Keypair) {
+  const [coreState] = await getCoreState(program.programId, admin.publicKey);
+  const [commitmentQueue] = await getCommitmentQueue(program.programId, admin.publicKey);
+  await program.rpc.initCommitmentQueue({
+    accounts: {
+      coreState,
+      admin: admin.publicKey,
+      commitmentQueue,
+      systemProgram: SystemProgram.programId
+    },
+    signers: [admin]
+  });
+  return commitmentQueue;
+}
+
+export async function pushCommitments(admin: PublicKey, executer: Keypair, commitments: Buffer[]) {
+  const [coreState] = await getCoreState(program.programId, admin);
+  const [commitmentQueue] = await getCommitmentQueue(program.programId, admin);
+  await program.rpc.pushCommitments({
+    commitments: commitments.map(c => Array.from(c))
+  }, {
+    accounts: {
+      executer: executer.publicKey,
+      coreState,
+      commitmentQueue
+    },
+    signers: [executer]
+  });
+}
+
+export async function deposit(admin: Keypair, tokenMint: PublicKey, amount: number) {
+  const [coreState] = await getCoreState(program.programId, admin.publicKey);
+  const [vaultAuthority] = await getVaultAuth(program.programId, admin.publicKey);
+  
+  const treasurerTokenAccount = (tokenMint.toBase58() === NATIVE_MINT.toBase58()) ?
+    admin.publicKey : (await getAssociatedTokenAddress(tokenMint, admin.publicKey));
+  let vaultTokenAccount;
+  if (tokenMint.toBase58() === NATIVE_MINT.toBase58()) {
+    vaultTokenAccount = vaultAuthority;
+  } else {
+    const [_vaultTokenAccount] = await getVaultTokenAccount(program.programId, tokenMint, admin.publicKey);
+    vaultTokenAccount = _vaultTokenAccount;
+  }
+    
+  await program.rpc.deposit({
+    amount: new anchor.BN(amount)
+  }, {
+    accounts: {
+      coreState,
+      treasurer: admin.publicKey,
+      vaultAuthority,
+      tokenMint,
+      treasurerTokenAccount,
+      vaultTokenAccount,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+    },
+    signers: [admin]
+  });
+}
+
+export async function withdraw(admin: Keypair, tokenMint: PublicKey, amount: number, treasurer: Keypair = admin, destination: PublicKey = treasurer.publicKey) {
+  const [coreState] = await getCoreState(program.programId, admin.publicKey);
+  const [vaultAuthority] = await getVaultAuth(program.programId, admin.publicKey);
+  
+  const destinationTokenAccount = (tokenMint.toBase58() === NATIVE_MINT.toBase58()) ?
+    destination : (await getAssociatedTokenAddress(tokenMint, destination));
+  let vaultTokenAccount;
+  if (tokenMint.toBase58() === NATIVE_MINT.toBase58()) {
+    vaultTokenAccount = vaultAuthority;
+  } else {
+    let [_vaultTokenAccount] = await getVaultTokenAccount(program.programId, tokenMint, admin.publicKey);
+    vaultTokenAccount = _vaultTokenAccount;
+  }
+  const [mintLedger] = await getMintLedger(program.programId, tokenMint, admin.publicKey);
+  
+  await program.rpc.withdraw({
+    amount: new anchor.BN(amount)
+  }, {
+    accounts: {
+      coreState,
+      treasurer: treasurer.publicKey,
+      vaultAuthority,
+      tokenMint,
+      destination,
+      destinationTokenAccount,
+      vaultTokenAccount,
+      mintLedger,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+    },
+    signers: [treasurer]
+  });
+}
+
+export async function claimFees(admin: Keypair, tokenMint: PublicKey, amount: number, treasurer: Keypair = admin, destination: PublicKey = treasurer.publicKey) {
+  const [coreState] = await getCoreState(program.programId, admin.publicKey);
+  const [vaultAuthority] = await getVaultAuth(program.programId, admin.publicKey);
+  const [mintLedger] = await getMintLedger(program.programId, tokenMint, admin.publicKey);
+  const feeVault = await getFeeVault(tokenMint, admin.publicKey);
+
+  const destinationTokenAccount = (tokenMint.toBase58() === NATIVE_MINT.toBase58()) ?
+    destination : (await getAssociatedTokenAddress(tokenMint, destination));
+
+  await program.rpc.claimFees({
+    amount: new anchor.BN(amount)
+  }, {
+    accounts: {
+      coreState,
+      treasurer: treasurer.publicKey,
+      vaultAuthority,
+      tokenMint,
+      destination,
+      destinationTokenAccount,
+      feeVault,
+      mintLedger,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+    },
+    signers: [treasurer]
+  });
+}
+
+export async function registerReferral(house: PublicKey, user: Keypair, referrer: PublicKey) {
+  const [coreState] = await getCoreState(program.programId, house);
+  const [referralLink] = await getReferralLink(program.programId, user.publicKey, house);
+  await program.rpc.registerReferral({
+    referrer
+  }, {
+    accounts: {
+      user: user.publicKey,
+      coreState,
+      referralLink,
+      systemProgram: SystemProgram.programId
+    },
+    signers: [user]
+  });
+}
+
+export async function initReferrer(house: PublicKey, referrer: Keypair, tokenMint: PublicKey) {
+  const [coreState] = await getCoreState(program.programId, house);
+  const [referrerAccount] = await getReferrer(program.programId, tokenMint, referrer.publicKey, house);
+  await program.rpc.initReferrer({
+    accounts: {
+      referrer: referrer.publicKey,
+      coreState,
+      tokenMint,
+      referrerAccount,
+      systemProgram: SystemProgram.programId
+    },
+    signers: [referrer]
+  });
+  return referrerAccount;
+}
+
+export async function claimReferralRewards(house: PublicKey, referrer: Keypair, tokenMint: PublicKey) {
+  const [coreState] = await getCoreState(program.programId, house);
+  const [vaultAuthority] = await getVaultAuth(program.programId, house);
+  const [referrerAccount] = await getReferrer(program.programId, tokenMint, referrer.publicKey, house);
+  const [mintLedger] = await getMintLedger(program.programId, tokenMint, house);
+  const feeVault = await getFeeVault(tokenMint, house);
+
+  const referrerTokenAccount = (tokenMint.toBase58() === NATIVE_MINT.toBase58()) ?
+    referrer.publicKey : (await getAssociatedTokenAddress(tokenMint, referrer.publicKey));
+
+  await program.rpc.claimReferralRewards({
+    accounts: {
+      referrer: referrer.publicKey,
+      coreState,
+      vaultAuthority,
+      tokenMint,
+      referrerTokenAccount,
+      referrerAccount,
+      feeVault,
+      mintLedger,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+    },
+    signers: [referrer]
+  });
+}
+
+export async function setLoyaltyMint(admin: Keypair, ratePercent: number, pointsPerFreeBet: number) {
+  const [coreState] = await getCoreState(program.programId, admin.publicKey);
+  const [vaultAuthority] = await getVaultAuth(program.programId, admin.publicKey);
+  const [loyaltyMint] = await getLoyaltyMint(program.programId, admin.publicKey);
+  await program.rpc.setLoyaltyMint({
+    loyaltyRateBps: ratePercent * 100,
+    pointsPerFreeBet: new anchor.BN(pointsPerFreeBet)
   }, {
     accounts: {
       admin: admin.publicKey,
-      executer: executer.publicKey,
       coreState,
       vaultAuthority,
-      systemProgram: SystemProgram.programId
+      loyaltyMint,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+      rent: SYSVAR_RENT_PUBKEY
     },
     signers: [admin]
   });
-  return { coreState, vaultAuthority };
+  return loyaltyMint;
+}
+
+export async function claimLoyaltyPoints(house: PublicKey, user: Keypair) {
+  const [coreState] = await getCoreState(program.programId, house);
+  const [vaultAuthority] = await getVaultAuth(program.programId, house);
+  const [playerStats] = await getPlayerStats(program.programId, user.publicKey, house);
+  const { loyaltyMint } = await program.account.coreState.fetch(coreState);
+  const userLoyaltyAccount = await getAssociatedTokenAddress(loyaltyMint, user.publicKey);
+  await program.rpc.claimLoyaltyPoints({
+    accounts: {
+      user: user.publicKey,
+      coreState,
+      vaultAuthority,
+      loyaltyMint,
+      userLoyaltyAccount,
+      playerStats,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+      rent: SYSVAR_RENT_PUBKEY
+    },
+    signers: [user]
+  });
+  return userLoyaltyAccount;
+}
+
+export async function redeemLoyaltyPoints(house: PublicKey, user: Keypair, freeBets: number) {
+  const [coreState] = await getCoreState(program.programId, house);
+  const [playerStats] = await getPlayerStats(program.programId, user.publicKey, house);
+  const { loyaltyMint } = await program.account.coreState.fetch(coreState);
+  const userLoyaltyAccount = await getAssociatedTokenAddress(loyaltyMint, user.publicKey);
+  await program.rpc.redeemLoyaltyPoints({
+    freeBets: new anchor.BN(freeBets)
+  }, {
+    accounts: {
+      user: user.publicKey,
+      coreState,
+      loyaltyMint,
+      userLoyaltyAccount,
+      playerStats,
+      tokenProgram: TOKEN_PROGRAM_ID
+    },
+    signers: [user]
+  });
 }
 
-export async function register(admin: Keypair, tokenMint: PublicKey, amounts: number[]) {
+export async function initLpPool(admin: Keypair, tokenMint: PublicKey) {
   const [coreState] = await getCoreState(program.programId, admin.publicKey);
   const [vaultAuthority] = await getVaultAuth(program.programId, admin.publicKey);
-  const [vaultTokenAccount, vaultTokenAccountNonce] = await getVaultTokenAccount(program.programId, tokenMint, admin.publicKey);
-  const [allowed] = await getAllowed(program.programId, tokenMint, admin.publicKey);
-  await program.rpc.register({
-    vaultTokenAccountNonce,
-    amounts: amounts.map(i => new anchor.BN(i))
-  }, {
+  const [lpMint] = await getLpMint(program.programId, tokenMint, admin.publicKey);
+  const [mintLedger] = await getMintLedger(program.programId, tokenMint, admin.publicKey);
+  const adminLpAccount = await getAssociatedTokenAddress(lpMint, admin.publicKey);
+  let vaultTokenAccount;
+  if (tokenMint.toBase58() === NATIVE_MINT.toBase58()) {
+    vaultTokenAccount = vaultAuthority;
+  } else {
+    const [_vaultTokenAccount] = await getVaultTokenAccount(program.programId, tokenMint, admin.publicKey);
+    vaultTokenAccount = _vaultTokenAccount;
+  }
+
+  await program.rpc.initLpPool({
     accounts: {
-      allowedBets:
-      allowed,
       coreState,
       admin: admin.publicKey,
       tokenMint,
-      vaultTokenAccount,
       vaultAuthority,
+      lpMint,
+      adminLpAccount,
+      vaultTokenAccount,
+      mintLedger,
       systemProgram: SystemProgram.programId,
       tokenProgram: TOKEN_PROGRAM_ID,
+      associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
       rent: SYSVAR_RENT_PUBKEY
     },
     signers: [admin]
   });
+  return lpMint;
 }
 
-export async function deposit(admin: Keypair, tokenMint: PublicKey, amount: number) {
-  const [coreState] = await getCoreState(program.programId, admin.publicKey);
-  const [vaultAuthority] = await getVaultAuth(program.programId, admin.publicKey);
-  
-  const adminTokenAccount = (tokenMint.toBase58() === NATIVE_MINT.toBase58()) ?
-    admin.publicKey : (await getAssociatedTokenAddress(tokenMint, admin.publicKey));
+export async function addLiquidity(admin: PublicKey, provider: Keypair, tokenMint: PublicKey, amount: number) {
+  const [coreState] = await getCoreState(program.programId, admin);
+  const [vaultAuthority] = await getVaultAuth(program.programId, admin);
+  const [lpMint] = await getLpMint(program.programId, tokenMint, admin);
+  const [mintLedger] = await getMintLedger(program.programId, tokenMint, admin);
+  const providerLpAccount = await getAssociatedTokenAddress(lpMint, provider.publicKey);
+
+  const providerTokenAccount = (tokenMint.toBase58() === NATIVE_MINT.toBase58()) ?
+    provider.publicKey : (await getAssociatedTokenAddress(tokenMint, provider.publicKey));
   let vaultTokenAccount;
   if (tokenMint.toBase58() === NATIVE_MINT.toBase58()) {
     vaultTokenAccount = vaultAuthority;
   } else {
-    const [_vaultTokenAccount] = await getVaultTokenAccount(program.programId, tokenMint, admin.publicKey);
+    const [_vaultTokenAccount] = await getVaultTokenAccount(program.programId, tokenMint, admin);
     vaultTokenAccount = _vaultTokenAccount;
   }
-    
-  await program.rpc.deposit({
+
+  await program.rpc.addLiquidity({
     amount: new anchor.BN(amount)
   }, {
     accounts: {
       coreState,
-      admin: admin.publicKey,
+      provider: provider.publicKey,
       vaultAuthority,
       tokenMint,
-      adminTokenAccount,
+      providerTokenAccount,
       vaultTokenAccount,
+      lpMint,
+      providerLpAccount,
+      mintLedger,
       tokenProgram: TOKEN_PROGRAM_ID,
       systemProgram: SystemProgram.programId,
     },
-    signers: [admin]
+    signers: [provider]
   });
 }
 
-export async function withdraw(admin: Keypair, tokenMint: PublicKey, amount: number) {
-  const [coreState] = await getCoreState(program.programId, admin.publicKey);
-  const [vaultAuthority] = await getVaultAuth(program.programId, admin.publicKey);
-  
-  const adminTokenAccount = (tokenMint.toBase58() === NATIVE_MINT.toBase58()) ?
-    admin.publicKey : (await getAssociatedTokenAddress(tokenMint, admin.publicKey));
+export async function removeLiquidity(admin: PublicKey, provider: Keypair, tokenMint: PublicKey, shares: number) {
+  const [coreState] = await getCoreState(program.programId, admin);
+  const [vaultAuthority] = await getVaultAuth(program.programId, admin);
+  const [lpMint] = await getLpMint(program.programId, tokenMint, admin);
+  const [mintLedger] = await getMintLedger(program.programId, tokenMint, admin);
+  const providerLpAccount = await getAssociatedTokenAddress(lpMint, provider.publicKey);
+
+  const providerTokenAccount = (tokenMint.toBase58() === NATIVE_MINT.toBase58()) ?
+    provider.publicKey : (await getAssociatedTokenAddress(tokenMint, provider.publicKey));
   let vaultTokenAccount;
   if (tokenMint.toBase58() === NATIVE_MINT.toBase58()) {
     vaultTokenAccount = vaultAuthority;
   } else {
-    let [_vaultTokenAccount] = await getVaultTokenAccount(program.programId, tokenMint, admin.publicKey);
+    const [_vaultTokenAccount] = await getVaultTokenAccount(program.programId, tokenMint, admin);
     vaultTokenAccount = _vaultTokenAccount;
   }
-  
-  await program.rpc.withdraw({
-    amount: new anchor.BN(amount)
+
+  await program.rpc.removeLiquidity({
+    shares: new anchor.BN(shares)
   }, {
     accounts: {
       coreState,
-      admin: admin.publicKey,
+      provider: provider.publicKey,
       vaultAuthority,
       tokenMint,
-      adminTokenAccount,
+      providerTokenAccount,
       vaultTokenAccount,
+      lpMint,
+      providerLpAccount,
+      mintLedger,
       tokenProgram: TOKEN_PROGRAM_ID,
       systemProgram: SystemProgram.programId,
     },
-    signers: [admin]
+    signers: [provider]
   });
 }
 
-export async function betDirectly(admin: PublicKey, user: Keypair, tokenMint: PublicKey, amount: number, betSide: boolean) {
+export async function betDirectly(admin: PublicKey, user: Keypair, tokenMint: PublicKey, amount: number, betSide: boolean, referrer: PublicKey | null = null, postInstructions: TransactionInstruction[] = []) {
   const [coreState] = await getCoreState(program.programId, admin);
+  const feeVault = await getFeeVault(tokenMint, admin);
   const [vaultAuthority] = await getVaultAuth(program.programId, admin);
   
   const userTokenAccount = (tokenMint.toBase58() === NATIVE_MINT.toBase58()) ?
@@ -135,28 +807,43 @@ export async function betDirectly(admin: PublicKey, user: Keypair, tokenMint: Pu
   }
 
   const [allowed, allowedNonce] = await getAllowed(program.programId, tokenMint, admin);
-  await program.rpc.betDirectly({
+  const [mintLedger] = await getMintLedger(program.programId, tokenMint, admin);
+  const [userExposure] = await getUserExposure(program.programId, tokenMint, user.publicKey, admin);
+  const [playerStats] = await getPlayerStats(program.programId, user.publicKey, admin);
+  const tx = new Transaction();
+  tx.add(program.instruction.betDirectly({
     amount: new anchor.BN(amount),
     betSide,
-    allowedAmountsNonce: allowedNonce
+    allowedAmountsNonce: allowedNonce,
+    referrer
   }, {
     accounts: {
       coreState,
       allowedBets: allowed,
+      mintLedger,
+      userExposure,
+      playerStats,
       user: user.publicKey,
       vaultAuthority,
       tokenMint,
       userTokenAccount,
       vaultTokenAccount,
+      feeVault,
+      slotHashes: SYSVAR_SLOT_HASHES_PUBKEY,
+      instructions: SYSVAR_INSTRUCTIONS_PUBKEY,
       tokenProgram: TOKEN_PROGRAM_ID,
       systemProgram: SystemProgram.programId
     },
-    signers: [user]
-  });
+    remainingAccounts: await referralAccounts(admin, user.publicKey, tokenMint, referrer)
+  }));
+  postInstructions.forEach(ix => tx.add(ix));
+  await program.provider.send(tx, [user]);
 }
 
-export async function bet(admin: PublicKey, user: Keypair, tokenMint: PublicKey, amount: number, betSide: boolean) {
+export async function bet(admin: PublicKey, user: Keypair, tokenMint: PublicKey, amount: number, betSide: boolean, clientSeed: Buffer, referrer: PublicKey | null = null) {
   const [coreState] = await getCoreState(program.programId, admin);
+  const feeVault = await getFeeVault(tokenMint, admin);
+  const commitmentQueue = await getBetCommitmentQueue(admin, user.publicKey);
   const [vaultAuthority] = await getVaultAuth(program.programId, admin);
   const flipCounter = parseInt((await program.account.coreState.fetch(coreState)).flipCounter);
   const [betState, betStateNonce] = await getBetState(program.programId, admin, user.publicKey, flipCounter);
@@ -171,36 +858,52 @@ export async function bet(admin: PublicKey, user: Keypair, tokenMint: PublicKey,
     vaultTokenAccount = _vaultTokenAccount;
   }
   const [allowed, allowedNonce] = await getAllowed(program.programId, tokenMint, admin);
+  const [mintLedger] = await getMintLedger(program.programId, tokenMint, admin);
+  const [userExposure] = await getUserExposure(program.programId, tokenMint, user.publicKey, admin);
+  const [playerStats] = await getPlayerStats(program.programId, user.publicKey, admin);
+  const [rentSponsor] = await getRentSponsor(program.programId, admin);
 
   await program.rpc.bet({
     amount: new anchor.BN(amount),
     betSide,
     flipCounter: new anchor.BN(flipCounter),
     betStateNonce,
-    allowedNonce
+    allowedNonce,
+    clientSeed: Array.from(clientSeed),
+    referrer
   }, {
     accounts: {
       coreState,
       allowedBets: allowed,
+      mintLedger,
+      userExposure,
+      playerStats,
       user: user.publicKey,
       vaultAuthority,
       tokenMint,
       userTokenAccount,
       vaultTokenAccount,
+      feeVault,
       betState,
+      commitmentQueue,
+      rentSponsor,
       tokenProgram: TOKEN_PROGRAM_ID,
       systemProgram: SystemProgram.programId,
       rent: SYSVAR_RENT_PUBKEY
     },
+    remainingAccounts: await referralAccounts(admin, user.publicKey, tokenMint, referrer),
     signers: [user]
   });
   return betState;
 }
 
-export async function betReturn(admin: Keypair, executer: Keypair, betState: PublicKey) {
+export async function betReturn(admin: Keypair, executer: Keypair, betState: PublicKey, secret: Buffer | null = null) {
   const [coreState] = await getCoreState(program.programId, admin.publicKey);
-  const {user, tokenMint } = (await program.account.betState.fetch(betState));
+  const { user, tokenMint, rentPayer } = (await program.account.betState.fetch(betState));
   const [vaultAuthority] = await getVaultAuth(program.programId, admin.publicKey);
+  const [mintLedger] = await getMintLedger(program.programId, tokenMint, admin.publicKey);
+  const [userExposure] = await getUserExposure(program.programId, tokenMint, user, admin.publicKey);
+  const [playerStats] = await getPlayerStats(program.programId, user, admin.publicKey);
   
   const userTokenAccount = (tokenMint.toBase58() === NATIVE_MINT.toBase58()) ?
     user : (await getAssociatedTokenAddress(tokenMint, user));
@@ -213,8 +916,113 @@ export async function betReturn(admin: Keypair, executer: Keypair, betState: Pub
   }
 
   await program.rpc.betReturn({
+    secret: secret ? Array.from(secret) : null
+  }, {
+    accounts: {
+      executer: executer.publicKey,
+      coreState,
+      user,
+      vaultAuthority,
+      tokenMint,
+      userTokenAccount,
+      vaultTokenAccount,
+      rentPayer,
+      betState,
+      mintLedger,
+      userExposure,
+      playerStats,
+      slotHashes: SYSVAR_SLOT_HASHES_PUBKEY,
+      instructions: SYSVAR_INSTRUCTIONS_PUBKEY,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+      rent: SYSVAR_RENT_PUBKEY
+    },
+    remainingAccounts: await settlementReferralAccounts(admin.publicKey, betState),
+    signers: [executer]
+  });
+  return betState;
+}
+
+export async function betDice(admin: PublicKey, user: Keypair, tokenMint: PublicKey, amount: number, target: number, rollOver: boolean, clientSeed: Buffer, referrer: PublicKey | null = null) {
+  const [coreState] = await getCoreState(program.programId, admin);
+  const feeVault = await getFeeVault(tokenMint, admin);
+  const commitmentQueue = await getBetCommitmentQueue(admin, user.publicKey);
+  const [vaultAuthority] = await getVaultAuth(program.programId, admin);
+  const flipCounter = parseInt((await program.account.coreState.fetch(coreState)).flipCounter);
+  const [betState, betStateNonce] = await getBetState(program.programId, admin, user.publicKey, flipCounter);
+  
+  const userTokenAccount = (tokenMint.toBase58() === NATIVE_MINT.toBase58()) ?
+    user.publicKey : (await getAssociatedTokenAddress(tokenMint, user.publicKey));
+  let vaultTokenAccount;
+  if (tokenMint.toBase58() === NATIVE_MINT.toBase58()) {
+    vaultTokenAccount = vaultAuthority;
+  } else {
+    let [_vaultTokenAccount] = await getVaultTokenAccount(program.programId, tokenMint, admin);
+    vaultTokenAccount = _vaultTokenAccount;
+  }
+  const [allowed, allowedNonce] = await getAllowed(program.programId, tokenMint, admin);
+  const [mintLedger] = await getMintLedger(program.programId, tokenMint, admin);
+  const [userExposure] = await getUserExposure(program.programId, tokenMint, user.publicKey, admin);
+  const [playerStats] = await getPlayerStats(program.programId, user.publicKey, admin);
+  const [rentSponsor] = await getRentSponsor(program.programId, admin);
+
+  await program.rpc.betDice({
+    amount: new anchor.BN(amount),
+    target,
+    rollOver,
+    flipCounter: new anchor.BN(flipCounter),
+    betStateNonce,
+    allowedNonce,
+    clientSeed: Array.from(clientSeed),
+    referrer
+  }, {
+    accounts: {
+      coreState,
+      allowedBets: allowed,
+      mintLedger,
+      userExposure,
+      playerStats,
+      user: user.publicKey,
+      vaultAuthority,
+      tokenMint,
+      userTokenAccount,
+      vaultTokenAccount,
+      feeVault,
+      betState,
+      commitmentQueue,
+      rentSponsor,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+      rent: SYSVAR_RENT_PUBKEY
+    },
+    remainingAccounts: await referralAccounts(admin, user.publicKey, tokenMint, referrer),
+    signers: [user]
+  });
+  return betState;
+}
+
+export async function betDiceReturn(admin: Keypair, executer: Keypair, betState: PublicKey, secret: Buffer | null = null) {
+  const [coreState] = await getCoreState(program.programId, admin.publicKey);
+  const { user, tokenMint, rentPayer } = (await program.account.betState.fetch(betState));
+  const [vaultAuthority] = await getVaultAuth(program.programId, admin.publicKey);
+  const [mintLedger] = await getMintLedger(program.programId, tokenMint, admin.publicKey);
+  const [userExposure] = await getUserExposure(program.programId, tokenMint, user, admin.publicKey);
+  const [playerStats] = await getPlayerStats(program.programId, user, admin.publicKey);
+  
+  const userTokenAccount = (tokenMint.toBase58() === NATIVE_MINT.toBase58()) ?
+    user : (await getAssociatedTokenAddress(tokenMint, user));
+  let vaultTokenAccount;
+  if (tokenMint.toBase58() === NATIVE_MINT.toBase58()) {
+    vaultTokenAccount = vaultAuthority;
+  } else {
+    let [_vaultTokenAccount] = await getVaultTokenAccount(program.programId, tokenMint, admin.publicKey);
+    vaultTokenAccount = _vaultTokenAccount;
+  }
+
+  await program.rpc.betDiceReturn({
+    secret: secret ? Array.from(secret) : null
+  }, {
     accounts: {
-      admin: admin.publicKey,
       executer: executer.publicKey,
       coreState,
       user,
@@ -222,23 +1030,214 @@ export async function betReturn(admin: Keypair, executer: Keypair, betState: Pub
       tokenMint,
       userTokenAccount,
       vaultTokenAccount,
+      rentPayer,
       betState,
+      mintLedger,
+      userExposure,
+      playerStats,
+      slotHashes: SYSVAR_SLOT_HASHES_PUBKEY,
+      instructions: SYSVAR_INSTRUCTIONS_PUBKEY,
       tokenProgram: TOKEN_PROGRAM_ID,
       systemProgram: SystemProgram.programId,
       rent: SYSVAR_RENT_PUBKEY
     },
+    remainingAccounts: await settlementReferralAccounts(admin.publicKey, betState),
     signers: [executer]
   });
   return betState;
 }
 
-export async function updateCoreState(admin: Keypair, feePercent: number, active: boolean, allowDirectBet: boolean) {
+export async function betReturnVrf(admin: Keypair, executer: Keypair, oracle: Keypair, betState: PublicKey) {
+  const [coreState] = await getCoreState(program.programId, admin.publicKey);
+  const { user, tokenMint, flipCounter, clientSeed, rentPayer } = (await program.account.betState.fetch(betState));
+  const [vaultAuthority] = await getVaultAuth(program.programId, admin.publicKey);
+  const [mintLedger] = await getMintLedger(program.programId, tokenMint, admin.publicKey);
+  const [userExposure] = await getUserExposure(program.programId, tokenMint, user, admin.publicKey);
+  const [playerStats] = await getPlayerStats(program.programId, user, admin.publicKey);
+
+  const userTokenAccount = (tokenMint.toBase58() === NATIVE_MINT.toBase58()) ?
+    user : (await getAssociatedTokenAddress(tokenMint, user));
+  let vaultTokenAccount;
+  if (tokenMint.toBase58() === NATIVE_MINT.toBase58()) {
+    vaultTokenAccount = vaultAuthority;
+  } else {
+    let [_vaultTokenAccount] = await getVaultTokenAccount(program.programId, tokenMint, admin.publicKey);
+    vaultTokenAccount = _vaultTokenAccount;
+  }
+
+  const message = Buffer.concat([
+    betState.toBuffer(),
+    (new anchor.BN(flipCounter)).toArrayLike(Buffer, "le", 8),
+    Buffer.from(clientSeed)
+  ]);
+  const tx = new Transaction();
+  tx.add(Ed25519Program.createInstructionWithPrivateKey({ privateKey: oracle.secretKey, message }));
+  tx.add(program.instruction.betReturnVrf({
+    accounts: {
+      executer: executer.publicKey,
+      coreState,
+      user,
+      vaultAuthority,
+      tokenMint,
+      userTokenAccount,
+      vaultTokenAccount,
+      rentPayer,
+      betState,
+      mintLedger,
+      userExposure,
+      playerStats,
+      slotHashes: SYSVAR_SLOT_HASHES_PUBKEY,
+      instructions: SYSVAR_INSTRUCTIONS_PUBKEY,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+      rent: SYSVAR_RENT_PUBKEY
+    },
+    remainingAccounts: await settlementReferralAccounts(admin.publicKey, betState)
+  }));
+  await program.provider.send(tx, [executer]);
+  return betState;
+}
+
+// pays out an expired, unrevealed CommitReveal bet; any signer can call it
+export async function settleExpiredBet(house: PublicKey, caller: Keypair, betState: PublicKey) {
+  const [coreState] = await getCoreState(program.programId, house);
+  const { user, tokenMint, rentPayer } = (await program.account.betState.fetch(betState));
+  const [vaultAuthority] = await getVaultAuth(program.programId, house);
+  const [mintLedger] = await getMintLedger(program.programId, tokenMint, house);
+  const [userExposure] = await getUserExposure(program.programId, tokenMint, user, house);
+  const [playerStats] = await getPlayerStats(program.programId, user, house);
+
+  const userTokenAccount = (tokenMint.toBase58() === NATIVE_MINT.toBase58()) ?
+    user : (await getAssociatedTokenAddress(tokenMint, user));
+  let vaultTokenAccount;
+  if (tokenMint.toBase58() === NATIVE_MINT.toBase58()) {
+    vaultTokenAccount = vaultAuthority;
+  } else {
+    let [_vaultTokenAccount] = await getVaultTokenAccount(program.programId, tokenMint, house);
+    vaultTokenAccount = _vaultTokenAccount;
+  }
+
+  await program.rpc.settleExpiredBet({
+    accounts: {
+      executer: caller.publicKey,
+      coreState,
+      user,
+      vaultAuthority,
+      tokenMint,
+      userTokenAccount,
+      vaultTokenAccount,
+      rentPayer,
+      betState,
+      mintLedger,
+      userExposure,
+      playerStats,
+      slotHashes: SYSVAR_SLOT_HASHES_PUBKEY,
+      instructions: SYSVAR_INSTRUCTIONS_PUBKEY,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+      rent: SYSVAR_RENT_PUBKEY
+    },
+    remainingAccounts: await settlementReferralAccounts(house, betState),
+    signers: [caller]
+  });
+  return betState;
+}
+
+export async function refundExpiredBet(admin: PublicKey, user: Keypair, betState: PublicKey) {
+  const [coreState] = await getCoreState(program.programId, admin);
+  const { tokenMint, rentPayer } = (await program.account.betState.fetch(betState));
+  const feeVault = await getFeeVault(tokenMint, admin);
+  const [vaultAuthority] = await getVaultAuth(program.programId, admin);
+  const [mintLedger] = await getMintLedger(program.programId, tokenMint, admin);
+  const [userExposure] = await getUserExposure(program.programId, tokenMint, user.publicKey, admin);
+  const [playerStats] = await getPlayerStats(program.programId, user.publicKey, admin);
+
+  const userTokenAccount = (tokenMint.toBase58() === NATIVE_MINT.toBase58()) ?
+    user.publicKey : (await getAssociatedTokenAddress(tokenMint, user.publicKey));
+  let vaultTokenAccount;
+  if (tokenMint.toBase58() === NATIVE_MINT.toBase58()) {
+    vaultTokenAccount = vaultAuthority;
+  } else {
+    let [_vaultTokenAccount] = await getVaultTokenAccount(program.programId, tokenMint, admin);
+    vaultTokenAccount = _vaultTokenAccount;
+  }
+
+  await program.rpc.refundExpiredBet({
+    accounts: {
+      user: user.publicKey,
+      coreState,
+      vaultAuthority,
+      tokenMint,
+      userTokenAccount,
+      vaultTokenAccount,
+      feeVault,
+      rentPayer,
+      betState,
+      mintLedger,
+      userExposure,
+      playerStats,
+      slotHashes: SYSVAR_SLOT_HASHES_PUBKEY,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId
+    },
+    signers: [user]
+  });
+}
+
+export type CoreStateConfig = {
+  active: boolean,
+  allowDirectBet: boolean,
+  randomnessMode: object,
+  oracle: PublicKey,
+  settleTimeout: number,
+  adminFundedRent: boolean,
+  payoutMultiplier: number,
+  maxRtpPercent: number,
+  diceEdgePercent: number,
+  referralSharePercent: number,
+};
+
+export function defaultCoreStateConfig(): CoreStateConfig {
+  return {
+    active: true,
+    allowDirectBet: true,
+    randomnessMode: RandomnessMode.CommitReveal,
+    oracle: PublicKey.default,
+    settleTimeout: 3600,
+    adminFundedRent: false,
+    payoutMultiplier: 2,
+    maxRtpPercent: 100,
+    diceEdgePercent: 2,
+    referralSharePercent: 0,
+  };
+}
+
+export async function updateCoreState(admin: Keypair, config: Partial<CoreStateConfig> = {}) {
   const [coreState] = await getCoreState(program.programId, admin.publicKey);
+  const {
+    active,
+    allowDirectBet,
+    randomnessMode,
+    oracle,
+    settleTimeout,
+    adminFundedRent,
+    payoutMultiplier,
+    maxRtpPercent,
+    diceEdgePercent,
+    referralSharePercent
+  } = { ...defaultCoreStateConfig(), ...config };
 
   await program.rpc.updateCoreState({
-    feePercent: new anchor.BN(feePercent * 100),
     active,
-    allowDirectBet
+    allowDirectBet,
+    oracle,
+    randomnessMode,
+    settleTimeout: new anchor.BN(settleTimeout),
+    adminFundedRent,
+    payoutMultiplierBps: Math.round(payoutMultiplier * 10000),
+    maxRtpBps: maxRtpPercent * 100,
+    diceEdgeBps: diceEdgePercent * 100,
+    referralShareBps: referralSharePercent * 100
   }, {
     accounts: {
       admin: admin.publicKey,
@@ -8,59 +8,199 @@ const CORE_STATE_SEED: string = "core-state";
 const VAULT_AUTH_SEED: string = "vault-auth";
 const VAULT_TOKEN_ACCOUNT_SEED: string = "vault-token-account";
 const BET_STATE_SEED: string = "bet-state";
+const COMMITMENT_QUEUE_SEED: string = "commitment-queue";
+const MINT_LEDGER_SEED: string = "mint-ledger";
+const RENT_SPONSOR_SEED: string = "rent-sponsor";
+const PENDING_CONFIG_SEED: string = "pending-config";
+const FEE_VAULT_SEED: string = "fee-vault";
+const REFERRAL_LINK_SEED: string = "referral-link";
+const REFERRER_SEED: string = "referrer";
+const PLAYER_STATS_SEED: string = "player-stats";
+const LP_MINT_SEED: string = "lp-mint";
+const USER_EXPOSURE_SEED: string = "user-exposure";
+const LOYALTY_MINT_SEED: string = "loyalty-mint";
 
 const program = anchor.workspace.CoinFlip as Program<CoinFlip>;
 
-export async function getCoreState(programId: PublicKey, admin: PublicKey) {
+export async function getCoreState(programId: PublicKey, house: PublicKey) {
   return await anchor.web3.PublicKey.findProgramAddress(
     [
       Buffer.from(anchor.utils.bytes.utf8.encode(CORE_STATE_SEED)),
-      admin.toBuffer()
+      house.toBuffer()
     ],
     programId
   );
 }
 
-export async function getAllowed(programId: PublicKey, mint: PublicKey, admin: PublicKey) {
+export async function getAllowed(programId: PublicKey, mint: PublicKey, house: PublicKey) {
     return await anchor.web3.PublicKey.findProgramAddress(
         [
             Buffer.from(anchor.utils.bytes.utf8.encode(ALLOWED)),
             mint.toBuffer(),
-            admin.toBuffer()
+            house.toBuffer()
         ],
         programId
     );
 }
 
-export async function getVaultAuth(programId: PublicKey, admin: PublicKey) {
+export async function getVaultAuth(programId: PublicKey, house: PublicKey) {
   return await anchor.web3.PublicKey.findProgramAddress(
     [
       Buffer.from(anchor.utils.bytes.utf8.encode(VAULT_AUTH_SEED)),
-      admin.toBuffer()
+      house.toBuffer()
     ],
     programId
   );
 }
 
-export async function getVaultTokenAccount(programId: PublicKey, tokenMint: PublicKey, admin: PublicKey) {
+export async function getVaultTokenAccount(programId: PublicKey, tokenMint: PublicKey, house: PublicKey) {
   return await anchor.web3.PublicKey.findProgramAddress(
     [
       Buffer.from(anchor.utils.bytes.utf8.encode(VAULT_TOKEN_ACCOUNT_SEED)),
       tokenMint.toBuffer(),
-      admin.toBuffer()
+      house.toBuffer()
     ],
     programId
   );
 }
 
-export async function getBetState(programId: PublicKey, admin: PublicKey, user: PublicKey, flipCounter: number) {
+export async function getBetState(programId: PublicKey, house: PublicKey, user: PublicKey, flipCounter: number) {
   return await anchor.web3.PublicKey.findProgramAddress(
     [
       Buffer.from(anchor.utils.bytes.utf8.encode(BET_STATE_SEED)),
-      admin.toBuffer(),
+      house.toBuffer(),
       user.toBuffer(),
       (new anchor.BN(flipCounter)).toArrayLike(Buffer, "le", 8)
     ],
     programId
   );
 }
+
+export async function getCommitmentQueue(programId: PublicKey, house: PublicKey) {
+  return await anchor.web3.PublicKey.findProgramAddress(
+    [
+      Buffer.from(anchor.utils.bytes.utf8.encode(COMMITMENT_QUEUE_SEED)),
+      house.toBuffer()
+    ],
+    programId
+  );
+}
+
+export async function getMintLedger(programId: PublicKey, mint: PublicKey, house: PublicKey) {
+  return await anchor.web3.PublicKey.findProgramAddress(
+    [
+      Buffer.from(anchor.utils.bytes.utf8.encode(MINT_LEDGER_SEED)),
+      mint.toBuffer(),
+      house.toBuffer()
+    ],
+    programId
+  );
+}
+
+export async function getRentSponsor(programId: PublicKey, house: PublicKey) {
+  return await anchor.web3.PublicKey.findProgramAddress(
+    [
+      Buffer.from(anchor.utils.bytes.utf8.encode(RENT_SPONSOR_SEED)),
+      house.toBuffer()
+    ],
+    programId
+  );
+}
+
+export async function getFeeVaultTokenAccount(programId: PublicKey, tokenMint: PublicKey, house: PublicKey) {
+  return await anchor.web3.PublicKey.findProgramAddress(
+    [
+      Buffer.from(anchor.utils.bytes.utf8.encode(FEE_VAULT_SEED)),
+      tokenMint.toBuffer(),
+      house.toBuffer()
+    ],
+    programId
+  );
+}
+
+export async function getNativeFeeVault(programId: PublicKey, house: PublicKey) {
+  return await anchor.web3.PublicKey.findProgramAddress(
+    [
+      Buffer.from(anchor.utils.bytes.utf8.encode(FEE_VAULT_SEED)),
+      house.toBuffer()
+    ],
+    programId
+  );
+}
+
+export async function getReferralLink(programId: PublicKey, user: PublicKey, house: PublicKey) {
+  return await anchor.web3.PublicKey.findProgramAddress(
+    [
+      Buffer.from(anchor.utils.bytes.utf8.encode(REFERRAL_LINK_SEED)),
+      user.toBuffer(),
+      house.toBuffer()
+    ],
+    programId
+  );
+}
+
+export async function getReferrer(programId: PublicKey, tokenMint: PublicKey, referrer: PublicKey, house: PublicKey) {
+  return await anchor.web3.PublicKey.findProgramAddress(
+    [
+      Buffer.from(anchor.utils.bytes.utf8.encode(REFERRER_SEED)),
+      tokenMint.toBuffer(),
+      referrer.toBuffer(),
+      house.toBuffer()
+    ],
+    programId
+  );
+}
+
+export async function getPlayerStats(programId: PublicKey, user: PublicKey, house: PublicKey) {
+  return await anchor.web3.PublicKey.findProgramAddress(
+    [
+      Buffer.from(anchor.utils.bytes.utf8.encode(PLAYER_STATS_SEED)),
+      user.toBuffer(),
+      house.toBuffer()
+    ],
+    programId
+  );
+}
+
+export async function getPendingConfig(programId: PublicKey, house: PublicKey) {
+  return await anchor.web3.PublicKey.findProgramAddress(
+    [
+      Buffer.from(anchor.utils.bytes.utf8.encode(PENDING_CONFIG_SEED)),
+      house.toBuffer()
+    ],
+    programId
+  );
+}
+
+export async function getLpMint(programId: PublicKey, mint: PublicKey, house: PublicKey) {
+  return await anchor.web3.PublicKey.findProgramAddress(
+    [
+      Buffer.from(anchor.utils.bytes.utf8.encode(LP_MINT_SEED)),
+      mint.toBuffer(),
+      house.toBuffer()
+    ],
+    programId
+  );
+}
+
+export async function getUserExposure(programId: PublicKey, mint: PublicKey, user: PublicKey, house: PublicKey) {
+  return await anchor.web3.PublicKey.findProgramAddress(
+    [
+      Buffer.from(anchor.utils.bytes.utf8.encode(USER_EXPOSURE_SEED)),
+      mint.toBuffer(),
+      user.toBuffer(),
+      house.toBuffer()
+    ],
+    programId
+  );
+}
+
+export async function getLoyaltyMint(programId: PublicKey, house: PublicKey) {
+  return await anchor.web3.PublicKey.findProgramAddress(
+    [
+      Buffer.from(anchor.utils.bytes.utf8.encode(LOYALTY_MINT_SEED)),
+      house.toBuffer()
+    ],
+    programId
+  );
+}